@@ -0,0 +1,76 @@
+use console::style;
+use ethers::utils::hex::encode;
+use ethers_providers::Middleware;
+use eyre::{eyre, Result};
+use std::path::PathBuf;
+
+/// The wallet a folder is created for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    /// ENS name the address was resolved from, if any
+    pub name: Option<String>,
+    /// Hex address (0x1Bca23...)
+    pub address: String,
+}
+
+impl Account {
+    /// Use a hex address as is.
+    pub fn from_hex(address: &str) -> Result<Self> {
+        if !address.starts_with("0x") {
+            return Err(eyre!(
+                "{} Supported formats are 0xabc12... or name.eth",
+                style("Invalid address").red()
+            ));
+        }
+        Ok(Account {
+            name: None,
+            address: address.to_string(),
+        })
+    }
+
+    /// Resolve an ENS name to its address through `provider`.
+    pub async fn from_ens<M: Middleware>(name: &str, provider: &M) -> Result<Self> {
+        let address = resolve_ens_name(name, provider).await?;
+        Ok(Account {
+            name: Some(name.to_string()),
+            address,
+        })
+    }
+
+    /// Parse `input` as either a hex address or an ENS name.
+    pub async fn resolve<M: Middleware>(input: &str, provider: &M) -> Result<Self> {
+        if is_ens_name(input) {
+            Account::from_ens(input, provider).await
+        } else {
+            Account::from_hex(input)
+        }
+    }
+
+    /// Folder the collection is saved to: `{base}/nft-folder/{name or address}`.
+    ///
+    /// `base` defaults to the user's picture directory, then the working directory.
+    pub fn folder(&self, base: Option<PathBuf>) -> PathBuf {
+        let mut path = base
+            .or_else(dirs::picture_dir)
+            .unwrap_or_else(|| PathBuf::from("."));
+        path.push("nft-folder");
+        match &self.name {
+            Some(name) => path.join(name),
+            None => path.join(&self.address),
+        }
+    }
+}
+
+/// Whether `input` looks like an ENS name (name.eth)
+pub fn is_ens_name(input: &str) -> bool {
+    input.split('.').next_back() == Some("eth")
+}
+
+/// Resolve an ENS name to a hex address
+pub async fn resolve_ens_name<M: Middleware>(ens_name: &str, provider: &M) -> Result<String> {
+    let address = provider
+        .resolve_name(ens_name)
+        .await
+        .map_err(|err| eyre!("Failed to resolve {ens_name}: {err}"))?;
+    Ok(format!("0x{}", encode(address)))
+}
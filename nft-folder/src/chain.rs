@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Networks tokens can be held on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Chain {
+    Ethereum,
+    Zora,
+    Base,
+    Optimism,
+}
+
+impl Chain {
+    pub const ALL: [Chain; 4] = [Chain::Ethereum, Chain::Zora, Chain::Base, Chain::Optimism];
+
+    /// EIP-155 chain id
+    pub fn id(&self) -> u64 {
+        match self {
+            Chain::Ethereum => 1,
+            Chain::Zora => 7777777,
+            Chain::Base => 8453,
+            Chain::Optimism => 10,
+        }
+    }
+
+    pub fn from_id(id: u64) -> Option<Chain> {
+        Chain::ALL.into_iter().find(|chain| chain.id() == id)
+    }
+
+    /// Subfolder tokens of this chain are saved to.
+    ///
+    /// Ethereum tokens stay at the root of the folder, where they have always been saved.
+    pub fn subfolder(&self) -> Option<String> {
+        match self {
+            Chain::Ethereum => None,
+            chain => Some(chain.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Chain::Ethereum => "ethereum",
+            Chain::Zora => "zora",
+            Chain::Base => "base",
+            Chain::Optimism => "optimism",
+        };
+        f.write_str(name)
+    }
+}
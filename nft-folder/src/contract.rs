@@ -0,0 +1,150 @@
+use crate::chain::Chain;
+use crate::token::{erc1155_uri, TokenStandard};
+use async_trait::async_trait;
+use ethers::contract::{abigen, ContractError};
+use ethers::types::{Address, U256};
+use ethers_providers::Middleware;
+use eyre::{Report, Result};
+use std::{error::Error, fmt::Display, sync::Arc};
+use tokio::sync::OnceCell;
+
+abigen!(
+    Erc721,
+    r#"[
+        function name() external view returns (string)
+        function ownerOf(uint256 tokenId) external view returns (address)
+        function tokenURI(uint256 tokenId) external view returns (string)
+    ]"#
+);
+abigen!(
+    Erc1155,
+    r#"[
+        function name() external view returns (string)
+        function balanceOf(address account, uint256 id) external view returns (uint256)
+        function uri(uint256 id) external view returns (string)
+    ]"#
+);
+
+/// `err` under `context`, kept as the report's source so that
+/// [`Retryable::classify`](crate::Retryable::classify) can tell dropped connections apart
+pub(crate) fn rpc_error<E>(context: impl Display, err: E) -> Report
+where
+    E: Error + Send + Sync + 'static,
+{
+    let message = format!("{context}: {err}");
+    Report::new(err).wrap_err(message)
+}
+
+/// [`rpc_error`] for contract calls, with the provider's error as the source
+pub(crate) fn call_error<M: Middleware + 'static>(
+    context: impl Display,
+    err: ContractError<M>,
+) -> Report {
+    match err {
+        ContractError::MiddlewareError { e } => rpc_error(context, e),
+        ContractError::ProviderError { e } => rpc_error(context, e),
+        err => rpc_error(context, err),
+    }
+}
+
+/// Reads token URIs from the token contracts
+#[async_trait]
+pub trait TokenUriReader: Send + Sync {
+    /// `tokenURI` for ERC-721, `uri` with `{id}` substituted for ERC-1155.
+    ///
+    /// Both are tried in turn when the standard is unknown.
+    async fn token_uri(
+        &self,
+        contract: Address,
+        id: U256,
+        standard: Option<TokenStandard>,
+    ) -> Result<String>;
+
+    /// Chain the contracts are read on, `None` if unsupported
+    async fn chain(&self) -> Result<Option<Chain>>;
+}
+
+/// [`TokenUriReader`] calling the contracts through an RPC provider.
+///
+/// Shared between the metadata resolver and the onchain source so the chain id is
+/// only requested once.
+pub struct ContractReader<M> {
+    provider: Arc<M>,
+    chain: OnceCell<Option<Chain>>,
+}
+
+impl<M> ContractReader<M> {
+    pub fn new(provider: Arc<M>) -> Self {
+        ContractReader {
+            provider,
+            chain: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn provider(&self) -> &Arc<M> {
+        &self.provider
+    }
+
+    /// Chain of the provider if already requested, see [`TokenUriReader::chain`]
+    pub(crate) fn known_chain(&self) -> Option<Option<Chain>> {
+        self.chain.get().copied()
+    }
+}
+
+impl<M: Middleware> ContractReader<M> {
+    pub(crate) fn erc721(&self, contract: Address) -> Erc721<M> {
+        Erc721::new(contract, Arc::clone(&self.provider))
+    }
+
+    pub(crate) fn erc1155(&self, contract: Address) -> Erc1155<M> {
+        Erc1155::new(contract, Arc::clone(&self.provider))
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> TokenUriReader for ContractReader<M> {
+    async fn token_uri(
+        &self,
+        contract: Address,
+        id: U256,
+        standard: Option<TokenStandard>,
+    ) -> Result<String> {
+        let erc721 = || async { self.erc721(contract).token_uri(id).call().await };
+        let erc1155 = || async {
+            self.erc1155(contract)
+                .uri(id)
+                .call()
+                .await
+                .map(|uri| erc1155_uri(&uri, id))
+        };
+        let uri = match standard {
+            Some(TokenStandard::Erc721) => erc721().await,
+            Some(TokenStandard::Erc1155) => erc1155().await,
+            None => match erc721().await {
+                Ok(uri) => Ok(uri),
+                Err(_) => erc1155().await,
+            },
+        };
+        uri.map_err(|err| {
+            call_error(
+                format!("Failed to read token URI of {id} on {contract:?}"),
+                err,
+            )
+        })
+    }
+
+    async fn chain(&self) -> Result<Option<Chain>> {
+        let chain = self
+            .chain
+            .get_or_try_init(|| async {
+                let id = self
+                    .provider
+                    .get_chainid()
+                    .await
+                    .map_err(|err| rpc_error("Failed to get chain id", err))?;
+                Ok::<_, eyre::Report>(Chain::from_id(id.as_u64()))
+            })
+            .await?;
+        Ok(*chain)
+    }
+}
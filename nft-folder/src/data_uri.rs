@@ -0,0 +1,149 @@
+use crate::metadata::image_url;
+use base64::decode;
+use eyre::{eyre, Result};
+use serde_json::Value;
+
+/// JSON documents followed from one data URI to the next before giving up
+const MAX_NESTING: usize = 4;
+
+/// Contents of an RFC 2397 `data:` URI
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataUri {
+    /// Lowercase media type without parameters, `text/plain` when left out
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+impl DataUri {
+    /// Parse `uri`, decoding base64 or percent-encoded data
+    pub fn parse(uri: &str) -> Result<Self> {
+        if !is_data_uri(uri) {
+            return Err(eyre!("Not a data URI"));
+        }
+        let (header, body) = uri[5..]
+            .split_once(',')
+            .ok_or_else(|| eyre!("Invalid data URI: no data"))?;
+        let mut parameters = header.split(';').map(str::trim);
+        let media_type = match parameters.next() {
+            Some(media_type) if media_type.contains('/') => media_type.to_lowercase(),
+            _ => "text/plain".to_string(),
+        };
+        let base64 = parameters.any(|parameter| parameter.eq_ignore_ascii_case("base64"));
+
+        let body = percent_decode(body);
+        let data = match base64 {
+            true => {
+                let body: Vec<u8> = body
+                    .into_iter()
+                    .filter(|byte| !byte.is_ascii_whitespace())
+                    .collect();
+                decode(body).map_err(|err| eyre!("Invalid base64 in data URI: {err}"))?
+            }
+            false => body,
+        };
+        Ok(DataUri { media_type, data })
+    }
+
+    /// Whether the data is JSON, as token metadata put on-chain is
+    pub fn is_json(&self) -> bool {
+        self.media_type == "application/json" || self.media_type.ends_with("+json")
+    }
+
+    pub fn json(&self) -> Result<Value> {
+        serde_json::from_slice(&self.data).map_err(|err| eyre!("Invalid JSON in data URI: {err}"))
+    }
+}
+
+/// Whether `uri` has the `data:` scheme, in any case
+pub fn is_data_uri(uri: &str) -> bool {
+    uri.get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// URI of the media itself, following metadata JSON given as a data URI to the
+/// media it names: its `animation_url` when `animated`, otherwise its image.
+pub fn media_uri(uri: String, animated: bool) -> Result<String> {
+    let mut uri = uri;
+    for _ in 0..MAX_NESTING {
+        if !is_data_uri(&uri) {
+            return Ok(uri);
+        }
+        let data = DataUri::parse(&uri)?;
+        if !data.is_json() {
+            return Ok(uri);
+        }
+        let metadata = data.json()?;
+        let animation = metadata
+            .get("animation_url")
+            .and_then(Value::as_str)
+            .filter(|url| animated && !url.is_empty())
+            .map(String::from);
+        uri = animation
+            .or_else(|| image_url(&metadata))
+            .ok_or_else(|| eyre!("No media found in JSON data URI"))?;
+    }
+    Err(eyre!("Data URI nested too deeply"))
+}
+
+/// Decode `%XX` escapes, keeping a `%` not followed by two hex digits as is
+fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decodes_base64_and_percent_encoding() {
+        let png = DataUri::parse("data:image/PNG;base64,iVBORw0K\nGgo=").unwrap();
+        assert_eq!(png.media_type, "image/png");
+        assert_eq!(png.data, b"\x89PNG\r\n\x1a\n");
+
+        let svg = DataUri::parse("data:image/svg+xml;utf8,%3Csvg width='100%'%3E").unwrap();
+        assert_eq!(svg.data, b"<svg width='100%'>");
+
+        let text = DataUri::parse("data:,Hello%2C%20World").unwrap();
+        assert_eq!(text.media_type, "text/plain");
+        assert_eq!(text.data, b"Hello, World");
+    }
+
+    #[test]
+    fn follows_json_to_media() {
+        let svg = "data:image/svg+xml;base64,PHN2Zy8+";
+        let json = format!("data:application/json,{}", json!({ "image": svg }));
+        assert_eq!(media_uri(json.clone(), true).unwrap(), svg);
+
+        let metadata = json!({ "image": json, "animation_url": "https://example.com/1.mp4" });
+        let nested = format!(
+            "data:application/json;base64,{}",
+            base64::encode(metadata.to_string())
+        );
+        assert_eq!(media_uri(nested.clone(), false).unwrap(), svg);
+        assert_eq!(
+            media_uri(nested, true).unwrap(),
+            "https://example.com/1.mp4"
+        );
+    }
+}
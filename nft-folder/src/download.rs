@@ -1,17 +1,31 @@
-use crate::request::{NftImage, NftToken};
+use crate::data_uri::{is_data_uri, media_uri, DataUri};
+use crate::embed::{embed, EmbeddedMetadata};
+use crate::ipfs::{GatewayPool, IpfsPath};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::media::{mime_extension, sniff, MediaSelection, EXTENSIONS, SNIFF_LENGTH};
+use crate::rasterize::{inline_references, render, Rasterize};
+use crate::retry::RetryPolicy;
+use crate::sidecar::{sidecar, sidecar_path, write_sidecar};
+use crate::token::{NftImage, NftToken};
 
-use base64::decode;
 use console::style;
 use eyre::{eyre, Result};
 use futures::stream::StreamExt;
-use reqwest::Client;
-use tokio::task::JoinHandle;
-use std::sync::Arc;
-use std::{fs, path::PathBuf};
+use reqwest::{header::CONTENT_TYPE, Client};
+use sha2::{Digest, Sha256};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use std::{
     fs::File,
     io::{self, ErrorKind, Write},
 };
+use tokio::task::JoinHandle;
 
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use tokio::sync::Semaphore;
@@ -27,176 +41,531 @@ fn pb_style(template: &str) -> ProgressStyle {
         .tick_strings(&["⣼", "⣹", "⢻", "⠿", "⡟", "⣏", "⣧", "⣶", "⣿"])
 }
 
+/// What the downloads of a run share
+#[derive(Clone)]
+pub struct DownloadContext {
+    pub client: Client,
+    pub retry: RetryPolicy,
+    /// Files saved in the folder, recorded as they are written
+    pub manifest: Arc<Mutex<Manifest>>,
+    /// Write each token's metadata beside its media
+    pub sidecars: bool,
+    /// Embed each token's title, creator and links into its image file
+    pub embed: bool,
+    /// Which of each token's media to save
+    pub media: MediaSelection,
+    /// Largest file downloaded, in bytes
+    pub max_size: Option<u64>,
+    /// Render saved SVGs to PNG
+    pub rasterize: Option<Rasterize>,
+    /// Gateways IPFS content is fetched through
+    pub gateways: GatewayPool,
+}
+
+impl DownloadContext {
+    pub fn new(client: Client) -> Self {
+        DownloadContext {
+            client,
+            retry: RetryPolicy::default(),
+            manifest: Arc::new(Mutex::new(Manifest::default())),
+            sidecars: true,
+            embed: false,
+            media: MediaSelection::default(),
+            max_size: None,
+            rasterize: None,
+            gateways: GatewayPool::default(),
+        }
+    }
+
+    fn record(&self, key: String, entry: ManifestEntry) {
+        self.manifest.lock().unwrap().insert(key, entry);
+    }
+}
+
+/// Prepare the download of a single token's media into `dir`.
+///
+/// The still is saved as `{name}.{ext}` and the animation as `{name} (animation).{ext}`,
+/// as selected by the context. Returns `Ok(None)` when nothing is left to download
+/// (files already exist or the media was embedded in the token), otherwise a handle
+/// to the spawned downloads,
+/// retried according to the context's policy. Saved files are added to its manifest,
+/// with the token's metadata written beside them when sidecars are enabled and
+/// into them when embedding is.
 pub fn handle_token(
     semaphore: Arc<Semaphore>,
     token: NftToken,
-    client: &Client,
+    context: &DownloadContext,
     mp: &MultiProgress,
-    dir: &PathBuf,
+    dir: &Path,
 ) -> Result<Option<JoinHandle<Result<()>>>> {
     // let debug_style = ProgressStyle::with_template("{wide_msg}").unwrap();
 
-    let image = token.image;
-    let name = if let Some(name) = token.name {
-        name
-    } else if let (Some(collection_name), Some(id)) = (&token.collection_name, &token.token_id) {
-        format!("{} #{}", collection_name, id)
-    } else {
+    let Some(name) = token.display_name() else {
         return Err(eyre!("Image data not found for {:#?}", token.token_id));
-    }
-    .replace("/", " ")
-    .replace("\\", " ");
-
-    let (url, mime) = match image {
+    };
+    let entry = ManifestEntry::new(&token);
+    let sidecar = context.sidecars.then(|| sidecar(&token));
+    let embedded = context.embed.then(|| EmbeddedMetadata::new(&token));
+    // Editions are saved once, but the count is kept visible
+    let msg = match token.balance {
+        Some(balance) if balance > 1 => format!("{name} (x{balance})"),
+        _ => name.clone(),
+    };
+    let animation = token.animation_url().map(String::from);
+    let image = match token.image {
         NftImage::Object {
             url,
             mime_type,
             size: _,
-        } => (url, mime_type),
-        NftImage::Url(url) => (url, None),
-        _ => return Err(eyre!("No image URL found for {name}")),
+        } => Some((url, mime_type)),
+        NftImage::Url(url) => Some((url, None)),
+        _ => None,
     };
-    let extension = if url.starts_with("data:image/svg") {
-        "svg".to_string()
-    } else if let Some(mime) = mime {
-        mime.rsplit("/").next().unwrap_or_default().to_string()
-    } else if url.starts_with("ipfs") {
-        // This is probably not going to be an image, but let's take a shot and see what happens
-        // println!("{} {}", name, url);
-        "png".to_string()
-    } else if url.starts_with("ens") {
-        // println!("{} {}", name, url);
-        return Err(eyre!("{name} is not an image"));
-    } else {
-        let ext = url.rsplit('.').next().unwrap_or_default().to_lowercase();
-        if ext.len() > 5 {
-            return Err(eyre!("No suitable extension found for {} {}", name, url));
-        } else {
-            ext
+    let media = context.media.select(image, animation);
+    if media.is_empty() {
+        return Err(eyre!("No image URL found for {name}"));
+    }
+
+    // Same-named tokens on other chains must not overwrite each other
+    let root = dir;
+    let dir = match token.chain.and_then(|chain| chain.subfolder()) {
+        Some(subfolder) => {
+            let dir = dir.join(subfolder);
+            fs::create_dir_all(&dir)?;
+            dir
         }
+        None => dir.to_path_buf(),
     };
-    // TODO: Timeout if download takes too long
-    // TODO: Maybe panic automatically on unrecognized file types
-    // TODO: Some SVGs seem to be having issues
+    let sidecar_path = sidecar_path(&dir, &name);
+    let sidecar_key = sidecar
+        .is_some()
+        .then(|| Manifest::key(root, &sidecar_path));
 
-    let file_path = dir.join(format!("{name}.{extension}"));
-    let msg = name.clone();
+    let mut handles = vec![];
+    for (url, mime, animated) in media {
+        let mut entry = entry.clone();
+        entry.sidecar = sidecar_key.clone();
+        let sidecar = sidecar.clone();
+        let sidecar_path = sidecar_path.clone();
+        // Metadata is only embedded into stills
+        let embedded = embedded.clone().filter(|_| !animated);
+        let (name, msg) = match animated {
+            true => (format!("{name} (animation)"), format!("{msg} (animation)")),
+            false => (name.clone(), msg.clone()),
+        };
+
+        let url = media_uri(url, animated)?;
+        // Media put on-chain is saved straight from the URI
+        let data = match is_data_uri(&url) {
+            true => Some(DataUri::parse(&url)?),
+            false => None,
+        };
+        match &data {
+            Some(data) => entry.mime_type = Some(data.media_type.clone()),
+            None => {
+                entry.source_url = Some(url.clone());
+                entry.mime_type = mime.clone();
+            }
+        }
+        // Downloads are named from their first bytes, this is only the fallback
+        let hint = if let Some(data) = &data {
+            sniff(&data.data)
+                .or_else(|| mime_extension(&data.media_type))
+                .filter(|extension| *extension != "json")
+        } else if url.starts_with("ens") {
+            return Err(eyre!("{name} is not an image"));
+        } else {
+            mime.as_deref()
+                .and_then(mime_extension)
+                .or_else(|| url_extension(&url))
+                // Not to be mistaken for the sidecar
+                .filter(|extension| *extension != "json")
+        };
+        // TODO: Timeout if download takes too long
+
+        // Downloads are renamed into place once complete, so an existing file is a complete one
+        if let Some(file_path) = saved_file(&dir, &name, hint) {
+            let key = Manifest::key(root, &file_path);
+            if let Some(sidecar) = sidecar.as_ref().filter(|_| !sidecar_path.is_file()) {
+                write_sidecar(&sidecar_path, sidecar)?;
+            }
+            let mut manifest = context.manifest.lock().unwrap();
+            match manifest.files.get_mut(&key) {
+                Some(saved) => {
+                    saved.sidecar = entry.sidecar.clone().or(saved.sidecar.take());
+                    // Editions are bought and sold without the file changing
+                    saved.balance = entry.balance;
+                }
+                // Files saved before the manifest existed are recorded as they are found
+                None => manifest.insert(key, entry.clone().hash_file(&file_path)?),
+            }
+            drop(manifest);
+            // Saved before rasterizing was asked for
+            if needs_raster(context, &file_path) {
+                handles.push(spawn_rasterize(
+                    &semaphore, context, root, file_path, entry, embedded,
+                ));
+            }
+            let pb = mp.insert(
+                0,
+                ProgressBar::new(100)
+                    .with_message(msg)
+                    .with_style(pb_style(INSTANT_TEMPLATE)),
+            );
+            pb.set_prefix("SKIPPED");
+            pb.finish();
+            continue;
+        }
+        // Media included in the token. Save and return
+        if let Some(data) = data {
+            let Some(extension) = hint else {
+                return Err(eyre!(
+                    "Unrecognized media type {} in data URI for {name}",
+                    data.media_type
+                ));
+            };
+            let file_path = dir.join(format!("{name}.{extension}"));
+            let key = Manifest::key(root, &file_path);
+            let pb = mp.insert(
+                0,
+                ProgressBar::new(100)
+                    .with_message(msg)
+                    .with_style(pb_style(INSTANT_TEMPLATE)),
+            );
+            let (size, sha256) = save_data(data.data, file_path.clone(), embedded.as_ref())?;
+            if let Some(sidecar) = &sidecar {
+                write_sidecar(&sidecar_path, sidecar)?;
+            }
+            context.record(key, entry.clone().saved(size, sha256));
+            pb.set_prefix("SAVED");
+            pb.finish();
+            if needs_raster(context, &file_path) {
+                handles.push(spawn_rasterize(
+                    &semaphore, context, root, file_path, entry, embedded,
+                ));
+            }
+            continue;
+        }
+
+        if DEBUG {
+            println!("Downloading {name} to {:?}", dir);
+        }
 
-    // TODO: Does not verify if file was saved correctly. Will skip over partially downloaded files
-    if file_path.is_file() {
-        let pb = mp.insert(
-            0,
-            ProgressBar::new(100)
-                .with_message(msg)
-                .with_style(pb_style(INSTANT_TEMPLATE)),
-        );
-        pb.set_prefix("SKIPPED");
-        pb.finish_with_message(format!("{name}"));
-        return Ok(None);
-    }
-    // SVG is included in response. Save and return
-    if url.starts_with("data:image/svg") {
         let pb = mp.insert(
             0,
             ProgressBar::new(100)
                 .with_message(msg)
-                .with_style(pb_style(INSTANT_TEMPLATE)),
+                .with_style(pb_style(BYTE_TEMPLATE)),
         );
-        decode_and_save(
-            &url.strip_prefix("data:image/svg+xml;base64,")
-                .unwrap_or(&url),
-            file_path,
-        )?;
-        pb.set_prefix("SAVED");
-        pb.finish();
-        return Ok(None);
-    }
-
-    if DEBUG {
-        println!("Downloading {name} to {:?}", file_path);
-    }
-
-    let pb = mp.insert(
-        0,
-        ProgressBar::new(100)
-            .with_message(msg)
-            .with_style(pb_style(BYTE_TEMPLATE)),
-    );
-
-    let url = if url.starts_with("ipfs") {
-        // append IPFS gateway
-        let hash = url
-            .split('/')
-            .into_iter()
-            .find(|&part| part.starts_with("Qm"));
-
-        match hash {
-            Some(hash) => format!("https://ipfs.io/ipfs/{}", hash),
-            None => {
-                // Handle the case where the hash is not found
-                pb.set_prefix(format!("{}", style("FAILED").fg(console::Color::Red)));
-                pb.abandon_with_message(format!("IPFS hash not found in URL for {name}"));
-                return Err(eyre::eyre!("IPFS hash not found in URL"));
-            }
+        if url.starts_with("ipfs") && IpfsPath::parse(&url).is_none() {
+            pb.set_prefix(format!("{}", style("FAILED").fg(console::Color::Red)));
+            pb.abandon_with_message(format!("Invalid IPFS URL for {name}"));
+            return Err(eyre!("Invalid IPFS URL {url}"));
         }
-    } else {
-        url.to_owned()
-    };
+        let semaphore = Arc::clone(&semaphore);
+        let context = context.clone();
+        let root = root.to_path_buf();
+        let base_path = dir.join(&name);
+        let msg = pb.message();
+        handles.push(tokio::spawn(async move {
+            let permit = semaphore.acquire_owned().await.unwrap();
 
-    let client = client.clone();
-    let handle = tokio::spawn(async move {
-        let permit = semaphore.acquire_owned().await.unwrap();
-
-        // pb.set_position(i);
-        let result = match download_image(&client, &url, &file_path, &pb).await {
-            Ok(()) => {
-                pb.set_prefix(format!(
-                    "{}",
-                    style("SAVED").fg(console::Color::Green)));
-                pb.finish_with_message(format!("{name}"));
-                Ok(())
-            }
-            Err(error) => {
-                pb.set_prefix(format!(
-                    "{}",
-                    style("FAILED").fg(console::Color::Red)
-                ));
-                pb.abandon_with_message(format!("{name}.{extension}: {error}"));
-                Err(eyre::eyre!("Error downloading image {}: {}", name, error))
+            let mut attempts = 0;
+            let downloaded = context
+                .retry
+                .run(|attempt| {
+                    attempts = attempt;
+                    if attempt > 1 {
+                        pb.set_prefix(format!(
+                            "{}",
+                            style(format!("RETRY {attempt}/{}", context.retry.max_attempts))
+                                .yellow()
+                        ));
+                        pb.set_position(0);
+                    }
+                    download_image(&context, &url, &base_path, hint, embedded.as_ref(), &pb)
+                })
+                .await
+                .and_then(|downloaded| {
+                    if let Some(sidecar) = &sidecar {
+                        write_sidecar(&sidecar_path, sidecar)?;
+                    }
+                    Ok(downloaded)
+                });
+            let result = match downloaded {
+                Ok(Downloaded {
+                    path: file_path,
+                    url,
+                    size,
+                    sha256,
+                }) => {
+                    let key = Manifest::key(&root, &file_path);
+                    entry.url = Some(url);
+                    context.record(key, entry.clone().saved(size, sha256));
+                    pb.set_prefix(format!("{}", style("SAVED").fg(console::Color::Green)));
+                    pb.finish_with_message(msg);
+                    match needs_raster(&context, &file_path) {
+                        true => {
+                            rasterize_saved(&context, &root, &file_path, entry, embedded.as_ref())
+                                .await
+                        }
+                        false => Ok(()),
+                    }
+                }
+                Err(error) => {
+                    let tried = match attempts {
+                        1 => String::new(),
+                        attempts => format!(" (after {attempts} attempts)"),
+                    };
+                    pb.set_prefix(format!("{}", style("FAILED").fg(console::Color::Red)));
+                    pb.abandon_with_message(format!("{name}: {error}{tried}"));
+                    Err(eyre::eyre!(
+                        "Error downloading image {}: {}{}",
+                        name,
+                        error,
+                        tried
+                    ))
+                }
+            };
+
+            drop(permit);
+            result
+        }));
+    }
+
+    // A token is settled once all of its media are
+    match handles.len() {
+        0 => Ok(None),
+        1 => Ok(handles.pop()),
+        _ => Ok(Some(tokio::spawn(async move {
+            let mut result = Ok(());
+            for handle in handles {
+                let saved = handle.await.map_err(Into::into).and_then(|saved| saved);
+                result = result.and(saved);
             }
-        };
+            result
+        }))),
+    }
+}
 
-        drop(permit);
-        result
-    });
-    Ok(Some(handle))
+/// A file saved by [`download_image`]
+struct Downloaded {
+    path: PathBuf,
+    /// URL the file came from, after gateway substitution
+    url: String,
+    size: u64,
+    sha256: Sha256,
 }
 
+/// Download `image_url` to `base_path` with the extension of its type.
+///
+/// The type is sniffed from the first bytes, then taken from the `Content-Type`
+/// header, then from `hint`. Files over the context's size limit are abandoned.
 async fn download_image(
-    client: &Client,
+    context: &DownloadContext,
     image_url: &str,
-    file_path: &PathBuf,
+    base_path: &Path,
+    hint: Option<&'static str>,
+    embedded: Option<&EmbeddedMetadata>,
     pb: &ProgressBar,
-) -> Result<()> {
-    let response = client.get(image_url).send().await?;
-    let content_length = response.content_length().unwrap_or(0);
+) -> Result<Downloaded> {
+    let (response, url) = context.gateways.get(&context.client, image_url).await?;
+    let content_length = response.content_length();
+    let max_size = context.max_size.unwrap_or(u64::MAX);
+    if let Some(length) = content_length.filter(|length| *length > max_size) {
+        return Err(eyre!("{length} bytes is over the {max_size} bytes limit"));
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(mime_extension);
     let mut byte_stream = response.bytes_stream();
-    pb.set_length(content_length);
+    pb.set_length(content_length.unwrap_or(0));
+
+    let part_path = part_path(base_path);
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    let written = async {
+        let mut file = File::create(&part_path)?;
+        let (mut written, mut hasher) = (0, Sha256::new());
+        while let Some(chunk) = byte_stream.next().await {
+            let chunk = chunk?;
+            if head.len() < SNIFF_LENGTH {
+                head.extend_from_slice(&chunk[..chunk.len().min(SNIFF_LENGTH - head.len())]);
+            }
+            file.write_all(&chunk).map_err(io::Error::other)?;
+            hasher.update(&chunk);
+            written += chunk.len() as u64;
+            if written > max_size {
+                return Err(eyre!("Over the {max_size} bytes limit"));
+            }
+            pb.inc(chunk.len() as u64);
+        }
+        file.sync_all()?;
+        Ok::<_, eyre::Report>((written, hasher))
+    }
+    .await;
 
-    // TODO: Check for an extension or get one from the header here
-    let mut file = File::create(file_path)?;
+    let result = match written {
+        Ok((written, _)) if content_length.is_some_and(|length| length != written) => {
+            Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Received {written} of {} bytes",
+                    content_length.unwrap_or_default()
+                ),
+            )
+            .into())
+        }
+        Ok(written) => match sniff(&head).or(content_type).or(hint) {
+            Some("json") => Err(eyre!("Received JSON metadata instead of media")),
+            Some(extension) => {
+                let mut file_path = base_path.as_os_str().to_owned();
+                file_path.push(format!(".{extension}"));
+                let file_path = PathBuf::from(file_path);
+                embed_file(&part_path, embedded, written).and_then(|(size, sha256)| {
+                    fs::rename(&part_path, &file_path)?;
+                    Ok(Downloaded {
+                        path: file_path,
+                        url,
+                        size,
+                        sha256,
+                    })
+                })
+            }
+            None => Err(eyre!("Unrecognized media type")),
+        },
+        Err(err) => Err(err),
+    };
+    if result.is_err() {
+        fs::remove_file(&part_path).ok();
+    }
+    result
+}
 
-    while let Some(chunk) = byte_stream.next().await {
-        let chunk = chunk?;
-        file.write_all(&chunk)
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+/// Whether the file at `path` is an SVG the context asks to rasterize, and not yet was
+fn needs_raster(context: &DownloadContext, path: &Path) -> bool {
+    context.rasterize.is_some()
+        && path.extension().is_some_and(|extension| extension == "svg")
+        && !path.with_extension("png").is_file()
+}
 
-        pb.inc(chunk.len() as u64);
+fn spawn_rasterize(
+    semaphore: &Arc<Semaphore>,
+    context: &DownloadContext,
+    root: &Path,
+    svg_path: PathBuf,
+    entry: ManifestEntry,
+    embedded: Option<EmbeddedMetadata>,
+) -> JoinHandle<Result<()>> {
+    let (semaphore, context) = (Arc::clone(semaphore), context.clone());
+    let root = root.to_path_buf();
+    tokio::spawn(async move {
+        let _permit = semaphore.acquire_owned().await.unwrap();
+        rasterize_saved(&context, &root, &svg_path, entry, embedded.as_ref()).await
+    })
+}
+
+/// Render the SVG saved at `svg_path` to a PNG beside it, recorded in the manifest
+/// with `entry`, then remove the SVG unless the context keeps it
+async fn rasterize_saved(
+    context: &DownloadContext,
+    root: &Path,
+    svg_path: &Path,
+    mut entry: ManifestEntry,
+    embedded: Option<&EmbeddedMetadata>,
+) -> Result<()> {
+    let Some(rasterize) = context.rasterize else {
+        return Ok(());
+    };
+    let rendered = async {
+        let svg = String::from_utf8_lossy(&fs::read(svg_path)?).into_owned();
+        let svg =
+            inline_references(&context.client, &context.gateways, &svg, context.max_size).await;
+        tokio::task::spawn_blocking(move || render(svg.as_bytes(), rasterize.width)).await?
     }
+    .await
+    .map_err(|err| eyre!("Error rasterizing {}: {err}", svg_path.display()))?;
 
+    let png_path = svg_path.with_extension("png");
+    let (size, sha256) = save_data(rendered, png_path.clone(), embedded)?;
+    entry.mime_type = Some("image/png".to_string());
+    context.record(Manifest::key(root, &png_path), entry.saved(size, sha256));
+    if !rasterize.keep_svg {
+        fs::remove_file(svg_path)?;
+        let key = Manifest::key(root, svg_path);
+        context.manifest.lock().unwrap().files.remove(&key);
+    }
     Ok(())
 }
 
+/// Extension at the end of the path of `url`, if it is one media is saved with
+fn url_extension(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let file = path.rsplit('/').next().unwrap_or_default();
+    let (_, extension) = file.rsplit_once('.')?;
+    match extension.to_lowercase().as_str() {
+        "jpeg" => Some("jpg"),
+        extension => EXTENSIONS.iter().copied().find(|known| *known == extension),
+    }
+}
+
+/// File of a previous run saved as `name` in `dir`, with any of the media extensions
+fn saved_file(dir: &Path, name: &str, hint: Option<&str>) -> Option<PathBuf> {
+    hint.into_iter()
+        .chain(EXTENSIONS.iter().copied())
+        .map(|extension| dir.join(format!("{name}.{extension}")))
+        .find(|path| path.is_file())
+}
+
+/// Embed `metadata` into the file at `path`, returning its size and hash.
+///
+/// Files in formats that can't hold it are left as `written`.
+fn embed_file(
+    path: &Path,
+    metadata: Option<&EmbeddedMetadata>,
+    written: (u64, Sha256),
+) -> Result<(u64, Sha256)> {
+    let Some(metadata) = metadata else {
+        return Ok(written);
+    };
+    let Some(embedded) = embed(&fs::read(path)?, metadata) else {
+        return Ok(written);
+    };
+    let mut file = File::create(path)?;
+    file.write_all(&embedded)?;
+    file.sync_all()?;
+    Ok((embedded.len() as u64, Sha256::new_with_prefix(&embedded)))
+}
+
+/// Where a file is written until complete: its path with a suffix unique to the
+/// write and `.part` appended, as tokens with the same name may be saved at once
+pub(crate) fn part_path(file_path: &Path) -> PathBuf {
+    static NEXT_PART: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_PART.fetch_add(1, Ordering::Relaxed);
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(format!(".{}-{id}.part", std::process::id()));
+    PathBuf::from(path)
+}
+
+/// Remove the `.part` files interrupted downloads left in `dir` and its subfolders.
+///
+/// Returns the number of files removed.
+pub fn remove_partial_files(dir: &Path) -> Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            removed += remove_partial_files(&path)?;
+        } else if path.extension().is_some_and(|ext| ext == "part") {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Create `dir_path` (and parents) if missing, failing if it exists as a file.
 pub async fn create_directory(dir_path: PathBuf) -> Result<PathBuf> {
     let copy = dir_path.clone();
     match fs::metadata(copy) {
@@ -219,18 +588,56 @@ pub async fn create_directory(dir_path: PathBuf) -> Result<PathBuf> {
     }
 }
 
-fn decode_and_save(base64_data: &str, file_path: PathBuf) -> Result<()> {
-    let decoded_data = decode(base64_data)?;
-    let mut file = File::create(file_path)?;
+fn save_data(
+    decoded_data: Vec<u8>,
+    file_path: PathBuf,
+    embedded: Option<&EmbeddedMetadata>,
+) -> Result<(u64, Sha256)> {
+    let decoded_data = match embedded.and_then(|metadata| embed(&decoded_data, metadata)) {
+        Some(embedded) => embedded,
+        None => decoded_data,
+    };
+    let part_path = part_path(&file_path);
+    let mut file = File::create(&part_path)?;
     file.write_all(&decoded_data)?;
-    Ok(())
+    file.sync_all()?;
+    fs::rename(part_path, file_path)?;
+    Ok((
+        decoded_data.len() as u64,
+        Sha256::new_with_prefix(&decoded_data),
+    ))
 }
 
 #[cfg(test)]
 mod tests {
-    /*
     use super::*;
 
+    #[test]
+    fn removes_partial_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("base")).unwrap();
+        for file in ["done.png", "cut.png.part", "base/cut.svg.part"] {
+            File::create(dir.join(file)).unwrap();
+        }
+
+        assert_eq!(remove_partial_files(dir).unwrap(), 2);
+        assert_eq!(saved_file(dir, "done", None), Some(dir.join("done.png")));
+        assert!(dir.join("done.png").is_file());
+        assert!(!dir.join("base/cut.svg.part").exists());
+    }
+
+    #[test]
+    fn writes_same_names_to_separate_parts() {
+        let (first, second) = (part_path(Path::new("noun")), part_path(Path::new("noun")));
+        assert_ne!(first, second);
+        for part in [first, second] {
+            assert!(part.extension().is_some_and(|ext| ext == "part"));
+            assert!(part.to_string_lossy().starts_with("noun."));
+        }
+    }
+
+    /*
     #[test]
     async fn resolve() {
         let provider: Provider<Http> = Provider::<Http>::try_from("https://eth.llamarpc.com");
@@ -0,0 +1,333 @@
+use crate::chain::Chain;
+use crate::media::{sniff, SNIFF_LENGTH};
+use crate::token::{NftToken, TokenStandard};
+use serde_json::Value;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// `VP8X` flag telling readers the file has an `XMP ` chunk
+const WEBP_XMP_FLAG: u8 = 0x04;
+const WEBP_ALPHA_FLAG: u8 = 0x10;
+
+/// Token details written into the saved files, for image viewers to show.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EmbeddedMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub creator: Option<String>,
+    /// `eip155:{chain id}/{erc721 or erc1155}:{contract}/{token id}`
+    pub identifier: Option<String>,
+    /// Page of the token on OpenSea
+    pub link: Option<String>,
+}
+
+impl EmbeddedMetadata {
+    pub fn new(token: &NftToken) -> Self {
+        let field = |keys: &[&str]| {
+            keys.iter().find_map(|key| {
+                token
+                    .metadata
+                    .as_ref()?
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .filter(|value| !value.is_empty())
+                    .map(String::from)
+            })
+        };
+        let chain = token.chain.unwrap_or(Chain::Ethereum);
+        let (identifier, link) = match (&token.collection_address, &token.token_id) {
+            (Some(contract), Some(id)) => {
+                let namespace = match token.standard {
+                    Some(TokenStandard::Erc1155) => "erc1155",
+                    _ => "erc721",
+                };
+                (
+                    Some(format!("eip155:{}/{namespace}:{contract}/{id}", chain.id())),
+                    Some(format!("https://opensea.io/assets/{chain}/{contract}/{id}")),
+                )
+            }
+            _ => (None, None),
+        };
+
+        EmbeddedMetadata {
+            title: token.display_name(),
+            description: field(&["description"]),
+            creator: field(&["created_by", "artist", "creator"]),
+            identifier,
+            link,
+        }
+    }
+
+    /// XMP packet with the Dublin Core properties that are set
+    fn xmp(&self) -> String {
+        let mut properties = String::new();
+        let mut alt = |name: &str, value: &Option<String>| {
+            if let Some(value) = value {
+                properties.push_str(&format!(
+                    "<dc:{name}><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:{name}>",
+                    escape(value)
+                ));
+            }
+        };
+        alt("title", &self.title);
+        alt("description", &self.description);
+        if let Some(creator) = &self.creator {
+            properties.push_str(&format!(
+                "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+                escape(creator)
+            ));
+        }
+        if let Some(identifier) = &self.identifier {
+            properties.push_str(&format!(
+                "<dc:identifier>{}</dc:identifier>",
+                escape(identifier)
+            ));
+        }
+        if let Some(link) = &self.link {
+            properties.push_str(&format!("<dc:source>{}</dc:source>", escape(link)));
+        }
+
+        format!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+             <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{properties}</rdf:Description>\
+             </rdf:RDF></x:xmpmeta>"
+        )
+    }
+
+    fn xmp_packet(&self) -> String {
+        format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>{}<?xpacket end=\"w\"?>",
+            self.xmp()
+        )
+    }
+}
+
+/// `file` with `metadata` embedded: XMP for JPEG, WebP and SVG, `iTXt` chunks for PNG.
+///
+/// `None` when the format isn't supported or the file can't be parsed.
+pub fn embed(file: &[u8], metadata: &EmbeddedMetadata) -> Option<Vec<u8>> {
+    let head = file.get(..SNIFF_LENGTH).unwrap_or(file);
+    match sniff(head)? {
+        "png" => embed_png(file, metadata),
+        "jpg" => embed_jpeg(file, metadata),
+        "webp" => embed_webp(file, metadata),
+        "svg" => embed_svg(file, metadata),
+        _ => None,
+    }
+}
+
+fn embed_png(file: &[u8], metadata: &EmbeddedMetadata) -> Option<Vec<u8>> {
+    // Text chunks go right before `IEND`
+    let mut offset = PNG_SIGNATURE.len();
+    let iend = loop {
+        let length = u32::from_be_bytes(file.get(offset..offset + 4)?.try_into().ok()?) as usize;
+        if file.get(offset + 4..offset + 8)? == b"IEND" {
+            break offset;
+        }
+        offset = offset.checked_add(length + 12)?;
+    };
+
+    let mut chunks = vec![];
+    let texts = [
+        ("Title", &metadata.title),
+        ("Description", &metadata.description),
+        ("Author", &metadata.creator),
+        ("Source", &metadata.link),
+        ("Identifier", &metadata.identifier),
+        ("XML:com.adobe.xmp", &Some(metadata.xmp_packet())),
+    ];
+    for (keyword, text) in texts {
+        let Some(text) = text else { continue };
+        // keyword, compression flag and method, empty language and translated keyword
+        let mut data = keyword.as_bytes().to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        chunks.extend(png_chunk(b"iTXt", &data));
+    }
+
+    let mut embedded = file[..iend].to_vec();
+    embedded.extend(chunks);
+    embedded.extend_from_slice(&file[iend..]);
+    Some(embedded)
+}
+
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let crc = crc32(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    chunk
+}
+
+/// CRC-32 (ISO-HDLC) as used by PNG chunks
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+fn embed_jpeg(file: &[u8], metadata: &EmbeddedMetadata) -> Option<Vec<u8>> {
+    let mut payload = JPEG_XMP_NAMESPACE.to_vec();
+    payload.extend_from_slice(metadata.xmp_packet().as_bytes());
+    let length = u16::try_from(payload.len() + 2).ok()?;
+
+    // After the JFIF `APP0` segment, which has to come first
+    let mut offset = 2;
+    while file.get(offset..offset + 2)? == [0xff, 0xe0] {
+        let segment = u16::from_be_bytes(file.get(offset + 2..offset + 4)?.try_into().ok()?);
+        offset += 2 + segment as usize;
+    }
+    file.get(offset..)?;
+
+    let mut embedded = file[..offset].to_vec();
+    embedded.extend_from_slice(&[0xff, 0xe1]);
+    embedded.extend_from_slice(&length.to_be_bytes());
+    embedded.extend(payload);
+    embedded.extend_from_slice(&file[offset..]);
+    Some(embedded)
+}
+
+fn embed_webp(file: &[u8], metadata: &EmbeddedMetadata) -> Option<Vec<u8>> {
+    let kind = file.get(12..16)?;
+    let data = file.get(20..)?;
+    let mut embedded = file[..12].to_vec();
+    match kind {
+        b"VP8X" => {
+            embedded.extend_from_slice(&file[12..]);
+            *embedded.get_mut(20)? |= WEBP_XMP_FLAG;
+        }
+        // Simple formats need an extended header to announce the XMP chunk
+        b"VP8 " | b"VP8L" => {
+            let (width, height, alpha) = match kind {
+                b"VP8 " => {
+                    if data.get(3..6)? != [0x9d, 0x01, 0x2a] {
+                        return None;
+                    }
+                    let width = u16::from_le_bytes(data.get(6..8)?.try_into().ok()?) & 0x3fff;
+                    let height = u16::from_le_bytes(data.get(8..10)?.try_into().ok()?) & 0x3fff;
+                    (width as u32, height as u32, false)
+                }
+                _ => {
+                    if *data.first()? != 0x2f {
+                        return None;
+                    }
+                    let bits = u32::from_le_bytes(data.get(1..5)?.try_into().ok()?);
+                    let (width, height) = ((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1);
+                    (width, height, (bits >> 28) & 1 == 1)
+                }
+            };
+            let mut header = vec![match alpha {
+                true => WEBP_XMP_FLAG | WEBP_ALPHA_FLAG,
+                false => WEBP_XMP_FLAG,
+            }];
+            header.extend_from_slice(&[0, 0, 0]);
+            header.extend_from_slice(&(width.checked_sub(1)?).to_le_bytes()[..3]);
+            header.extend_from_slice(&(height.checked_sub(1)?).to_le_bytes()[..3]);
+            embedded.extend(riff_chunk(b"VP8X", &header));
+            embedded.extend_from_slice(&file[12..]);
+        }
+        _ => return None,
+    }
+    embedded.extend(riff_chunk(b"XMP ", metadata.xmp_packet().as_bytes()));
+
+    let size = u32::try_from(embedded.len() - 8).ok()?;
+    embedded[4..8].copy_from_slice(&size.to_le_bytes());
+    Some(embedded)
+}
+
+fn riff_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    // Chunks are padded to an even size
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn embed_svg(file: &[u8], metadata: &EmbeddedMetadata) -> Option<Vec<u8>> {
+    let svg = std::str::from_utf8(file).ok()?;
+    let start = svg.find("<svg")?;
+    let end = start + svg[start..].find('>')?;
+    // `<svg/>` has no content to add metadata to
+    if svg[..end].ends_with('/') {
+        return None;
+    }
+    let mut embedded = String::with_capacity(svg.len() + 1024);
+    embedded.push_str(&svg[..=end]);
+    embedded.push_str(&format!("<metadata>{}</metadata>", metadata.xmp()));
+    embedded.push_str(&svg[end + 1..]);
+    Some(embedded.into_bytes())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> EmbeddedMetadata {
+        EmbeddedMetadata {
+            title: Some("Noun 1".to_string()),
+            description: Some("Heads & glasses".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn computes_png_crc() {
+        assert_eq!(png_chunk(b"IEND", &[])[8..], [0xae, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn adds_png_text_before_end() {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(png_chunk(b"IHDR", &[0; 13]));
+        png.extend(png_chunk(b"IEND", &[]));
+
+        let embedded = embed(&png, &metadata()).unwrap();
+        let title = png_chunk(b"iTXt", b"Title\0\0\0\0\0Noun 1");
+        assert_eq!(embedded[33..33 + title.len()], title);
+        assert!(embedded.ends_with(&png_chunk(b"IEND", &[])));
+    }
+
+    #[test]
+    fn adds_jpeg_segment_after_jfif() {
+        let jpeg = [0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xd9];
+        let embedded = embed(&jpeg, &metadata()).unwrap();
+        assert_eq!(embedded[8..10], [0xff, 0xe1]);
+        assert!(embedded.ends_with(&[0xff, 0xd9]));
+    }
+
+    #[test]
+    fn adds_svg_metadata_element() {
+        let svg = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg"><rect/></svg>"#;
+        let embedded = String::from_utf8(embed(svg, &metadata()).unwrap()).unwrap();
+        assert!(embedded.contains(r#"2000/svg"><metadata><x:xmpmeta"#));
+        assert!(embedded.contains("Heads &amp; glasses"));
+        assert!(embedded.ends_with("</metadata><rect/></svg>"));
+    }
+
+    #[test]
+    fn leaves_other_formats_alone() {
+        assert_eq!(embed(b"GIF89a\x01\x00\x01\x00", &metadata()), None);
+        let html = b"<!DOCTYPE html><html><body><svg></svg></body></html>";
+        assert_eq!(embed(html, &metadata()), None);
+    }
+}
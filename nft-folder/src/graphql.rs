@@ -0,0 +1,209 @@
+use crate::rate_limit::{self, RateLimit};
+use reqwest::{Client, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+
+/// Minimal GraphQL over HTTP client: parameterized queries and spec compliant errors
+#[derive(Debug, Clone)]
+pub struct GraphQlClient {
+    client: Client,
+    endpoint: String,
+    rate_limit: Option<RateLimit>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQlRequest<'a, V> {
+    query: &'a str,
+    operation_name: Option<&'a str>,
+    variables: V,
+}
+
+#[derive(Deserialize)]
+struct RawResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+/// Data returned by a query, along with the errors of any field that failed to resolve.
+///
+/// A non-empty `errors` means the data is partial: failed fields were nulled out.
+#[derive(Debug)]
+pub struct GraphQlResponse<T> {
+    pub data: T,
+    pub errors: Vec<GraphQlError>,
+}
+
+/// An entry of the response's `errors` array
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GraphQlError {
+    pub message: String,
+    #[serde(default)]
+    pub locations: Vec<ErrorLocation>,
+    #[serde(default)]
+    pub path: Vec<PathSegment>,
+    pub extensions: Option<serde_json::Value>,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ErrorLocation {
+    pub line: u64,
+    pub column: u64,
+}
+/// Field name or list index in the path of an error
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Field(String),
+    Index(u64),
+}
+
+impl fmt::Display for GraphQlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.path.is_empty() {
+            let path: Vec<String> = self
+                .path
+                .iter()
+                .map(|segment| match segment {
+                    PathSegment::Field(field) => field.clone(),
+                    PathSegment::Index(index) => index.to_string(),
+                })
+                .collect();
+            write!(f, " at {}", path.join("."))?;
+        }
+        for location in &self.locations {
+            write!(f, " ({}:{})", location.line, location.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a GraphQL query failed
+#[derive(Debug)]
+pub enum GraphQlClientError {
+    /// The request could not be sent or its response read
+    Transport(reqwest::Error),
+    /// Non-success status without a GraphQL error payload
+    Status { status: StatusCode, body: String },
+    /// The response is not a GraphQL response of the expected shape
+    Decode(serde_json::Error),
+    /// The query failed as a whole and returned no data
+    Query(Vec<GraphQlError>),
+    /// Neither data nor errors were returned
+    Empty,
+}
+
+impl fmt::Display for GraphQlClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphQlClientError::Transport(err) => write!(f, "Failed to send request: {err}"),
+            GraphQlClientError::Status { status, body } => {
+                write!(f, "Request failed with {status}: {body}")
+            }
+            GraphQlClientError::Decode(err) => write!(f, "Failed to parse JSON response: {err}"),
+            GraphQlClientError::Query(errors) => {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "Query failed: {}", errors.join("; "))
+            }
+            GraphQlClientError::Empty => write!(f, "Response contained no data"),
+        }
+    }
+}
+
+impl std::error::Error for GraphQlClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GraphQlClientError::Transport(err) => Some(err),
+            GraphQlClientError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl GraphQlClient {
+    pub fn new(client: Client, endpoint: impl Into<String>) -> Self {
+        GraphQlClient {
+            client,
+            endpoint: endpoint.into(),
+            rate_limit: None,
+        }
+    }
+
+    /// Limit the requests sent to the endpoint's host
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Run `query` with `variables`, deserializing its `data` into `T`.
+    ///
+    /// Responses with both data and errors are returned as partial data rather than failing.
+    pub async fn query<V: Serialize, T: DeserializeOwned>(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: V,
+    ) -> Result<GraphQlResponse<T>, GraphQlClientError> {
+        let request = self.client.post(&self.endpoint).json(&GraphQlRequest {
+            query,
+            operation_name,
+            variables,
+        });
+        let response = rate_limit::send(request, self.rate_limit)
+            .await
+            .map_err(GraphQlClientError::Transport)?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(GraphQlClientError::Transport)?;
+
+        // Servers may answer errors with 4xx statuses, the payload is more useful than the status
+        let response: RawResponse<T> = match serde_json::from_slice(&body) {
+            Ok(response) => response,
+            Err(_) if !status.is_success() => {
+                return Err(GraphQlClientError::Status {
+                    status,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                })
+            }
+            Err(err) => return Err(GraphQlClientError::Decode(err)),
+        };
+
+        match response.data {
+            Some(data) => Ok(GraphQlResponse {
+                data,
+                errors: response.errors,
+            }),
+            None if !response.errors.is_empty() => Err(GraphQlClientError::Query(response.errors)),
+            None => Err(GraphQlClientError::Empty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_error_array() {
+        let response: RawResponse<serde_json::Value> = serde_json::from_str(
+            r#"{
+                "data": {"tokens": {"nodes": [null]}},
+                "errors": [{
+                    "message": "Image unavailable",
+                    "locations": [{"line": 6, "column": 17}],
+                    "path": ["tokens", "nodes", 0, "token"]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(response.data.is_some());
+        assert_eq!(
+            response.errors[0].to_string(),
+            "Image unavailable at tokens.nodes.0.token (6:17)"
+        );
+    }
+}
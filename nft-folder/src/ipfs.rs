@@ -0,0 +1,443 @@
+use crate::metadata::gateway_url;
+use crate::rate_limit;
+use eyre::{eyre, Report, Result};
+use reqwest::{Client, Response, StatusCode};
+use std::{
+    io::{self, ErrorKind},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Gateway IPFS content is fetched through first
+pub const DEFAULT_GATEWAY: &str = "https://ipfs.io";
+/// Gateway tried when the first one fails
+pub const FALLBACK_GATEWAY: &str = "https://{cid}.ipfs.dweb.link";
+/// Time a gateway has to send the whole response before the next one is tried
+pub const DEFAULT_GATEWAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time a failing gateway is tried last, doubled with each further failure
+const BENCH_TIME: Duration = Duration::from_secs(30);
+const MAX_BENCH_TIME: Duration = Duration::from_secs(600);
+/// Public gateways whose URLs are fetched through the configured gateways instead
+const KNOWN_GATEWAYS: &[&str] = &[
+    "ipfs.io",
+    "gateway.ipfs.io",
+    "dweb.link",
+    "cloudflare-ipfs.com",
+    "cf-ipfs.com",
+    "gateway.pinata.cloud",
+    "nftstorage.link",
+    "w3s.link",
+    "ipfs.infura.io",
+    "infura-ipfs.io",
+    "4everland.io",
+    "ipfs.fleek.co",
+    "gateway.lighthouse.storage",
+];
+const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE32: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Content addressed on IPFS (or named on IPNS), with the path and query under it.
+///
+/// Parsed from `ipfs://{cid}/path`, `ipfs://ipfs/{cid}`, `ipfs:/ipfs/{cid}`,
+/// `/ipfs/{cid}`, `ipfs/{cid}`, a bare CID and the `ipns` equivalents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpfsPath {
+    /// `ipfs` or `ipns`
+    pub namespace: &'static str,
+    /// CIDv0 or CIDv1 for `ipfs`, name or key for `ipns`
+    pub root: String,
+    /// Path and query under the root, empty or starting with `/` or `?`
+    pub path: String,
+}
+
+impl IpfsPath {
+    pub fn parse(uri: &str) -> Option<Self> {
+        let uri = uri.trim();
+        let scheme = ["ipfs", "ipns"].into_iter().find(|scheme| {
+            uri.get(..scheme.len() + 1)
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{scheme}:")))
+        });
+        let rest = match scheme {
+            Some(scheme) => &uri[scheme.len() + 1..],
+            None => uri,
+        };
+        let rest = rest.trim_start_matches('/');
+
+        // `/ipfs/{cid}` has nothing else to tell it apart, `ipfs://ipfs/{cid}` repeats it
+        let (namespace, rest) = match rest.split_once('/') {
+            Some(("ipfs", rest)) => ("ipfs", rest),
+            Some(("ipns", rest)) => ("ipns", rest),
+            _ => (scheme.unwrap_or("ipfs"), rest),
+        };
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (root, path) = rest.split_at(end);
+        let valid = match namespace {
+            "ipfs" => is_cid(root),
+            _ => {
+                !root.is_empty()
+                    && root
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+            }
+        };
+        if !valid {
+            return None;
+        }
+        // Fragments are never sent to the server
+        let path = path.split('#').next().unwrap_or_default();
+        Some(IpfsPath {
+            namespace,
+            root: root.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Content `url` points at, if it is an IPFS URI or the URL of a well-known
+    /// public gateway, either path (`/ipfs/{cid}`) or subdomain (`{cid}.ipfs.`) style
+    pub fn from_url(url: &str) -> Option<Self> {
+        if let Some(path) = IpfsPath::parse(url) {
+            return Some(path);
+        }
+        let rest = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))?;
+        let (host, path) = rest.split_at(rest.find(['/', '?', '#']).unwrap_or(rest.len()));
+        let host = host.to_lowercase();
+        let known =
+            |host: &str| KNOWN_GATEWAYS.contains(&host) || host.ends_with(".mypinata.cloud");
+
+        match host.split_once(".ipfs.") {
+            Some((root, gateway)) if known(gateway) => {
+                IpfsPath::parse(&format!("ipfs://{root}{path}"))
+            }
+            None if known(&host) && (path.starts_with("/ipfs/") || path.starts_with("/ipns/")) => {
+                IpfsPath::parse(path)
+            }
+            _ => None,
+        }
+    }
+
+    /// Root as a DNS label, for subdomain gateways: CIDs in lowercase base32,
+    /// IPNS names with `-` doubled and `.` replaced by `-`
+    pub fn subdomain_label(&self) -> String {
+        if self.namespace == "ipns" {
+            return self.root.replace('-', "--").replace('.', "-");
+        }
+        let bytes = match self.root.split_at(1) {
+            // CIDv0 is the multihash of a dag-pb CIDv1
+            ("Q", _) => base58_decode(&self.root).map(|hash| [&[0x01, 0x70], &hash[..]].concat()),
+            ("z", encoded) => base58_decode(encoded),
+            ("B", encoded) => return format!("b{}", encoded.to_lowercase()),
+            _ => None,
+        };
+        match bytes {
+            Some(bytes) => format!("b{}", base32_encode(&bytes)),
+            None => self.root.clone(),
+        }
+    }
+
+    /// URL of the content on the path style `gateway`, such as `https://ipfs.io`
+    pub fn gateway_url(&self, gateway: &str) -> String {
+        format!(
+            "{}/{}/{}{}",
+            gateway.trim_end_matches('/'),
+            self.namespace,
+            self.root,
+            self.path
+        )
+    }
+}
+
+/// An IPFS HTTP gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Gateway {
+    /// `https://ipfs.io/ipfs/{cid}/path`, given as `https://ipfs.io`
+    Path(String),
+    /// `https://{cid}.ipfs.dweb.link/path`, given as such with the `{cid}` placeholder
+    Subdomain { scheme: String, host: String },
+}
+
+impl Gateway {
+    pub fn parse(url: &str) -> Result<Self> {
+        let url = url.trim().trim_end_matches('/');
+        let (scheme, rest) = url
+            .split_once("://")
+            .filter(|(scheme, _)| matches!(*scheme, "http" | "https"))
+            .ok_or_else(|| eyre!("Invalid gateway {url}: expected an http(s) URL"))?;
+        match rest.strip_prefix("{cid}.ipfs.") {
+            Some(host) if !host.is_empty() && !host.contains('/') => Ok(Gateway::Subdomain {
+                scheme: scheme.to_string(),
+                host: host.to_string(),
+            }),
+            _ if rest.contains("{cid}") => Err(eyre!(
+                "Invalid gateway {url}: subdomain gateways are written https://{{cid}}.ipfs.host"
+            )),
+            _ => Ok(Gateway::Path(url.to_string())),
+        }
+    }
+
+    /// URL of `content` on this gateway
+    pub fn url(&self, content: &IpfsPath) -> String {
+        match self {
+            Gateway::Path(base) => content.gateway_url(base),
+            Gateway::Subdomain { scheme, host } => format!(
+                "{scheme}://{}.{}.{host}{}",
+                content.subdomain_label(),
+                content.namespace,
+                content.path
+            ),
+        }
+    }
+}
+
+/// Ordered IPFS gateways, each tried in turn when the previous ones time out or
+/// answer with 429 or 5xx.
+///
+/// Failing gateways are tried last for a while, the health being shared by the
+/// clones of a pool for the length of a run.
+#[derive(Debug, Clone)]
+pub struct GatewayPool {
+    gateways: Arc<Vec<Gateway>>,
+    health: Arc<Mutex<Vec<Health>>>,
+    timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Health {
+    failures: u32,
+    benched_until: Option<Instant>,
+}
+
+impl Default for GatewayPool {
+    /// [`DEFAULT_GATEWAY`], then [`FALLBACK_GATEWAY`]
+    fn default() -> Self {
+        let gateways = [DEFAULT_GATEWAY, FALLBACK_GATEWAY]
+            .into_iter()
+            .filter_map(|url| Gateway::parse(url).ok())
+            .collect();
+        GatewayPool::new(gateways)
+    }
+}
+
+impl GatewayPool {
+    /// Pool trying `gateways` in order, the default ones if empty
+    pub fn new(gateways: Vec<Gateway>) -> Self {
+        if gateways.is_empty() {
+            return GatewayPool::default();
+        }
+        GatewayPool {
+            health: Arc::new(Mutex::new(vec![Health::default(); gateways.len()])),
+            gateways: Arc::new(gateways),
+            timeout: DEFAULT_GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Time a gateway has to send the whole response before the next one is tried (default 30s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send a GET request for `url`, returning the response and the URL it came from.
+    ///
+    /// IPFS content, including links to well-known gateways, is fetched through the
+    /// pool's gateways. Other URLs are requested as is, `ar://` through Arweave's gateway.
+    pub async fn get(&self, client: &Client, url: &str) -> Result<(Response, String)> {
+        let Some(content) = IpfsPath::from_url(url) else {
+            let url = gateway_url(url);
+            let response = rate_limit::send(client.get(&url), None)
+                .await?
+                .error_for_status()?;
+            return Ok((response, url));
+        };
+
+        let mut last_error = None;
+        for index in self.order() {
+            let url = self.gateways[index].url(&content);
+            // Covers reading the body as well, so stalled downloads are abandoned too
+            let request = client.get(&url).timeout(self.timeout);
+            let error: Report = match request.send().await {
+                Err(err) if err.is_timeout() => {
+                    let message =
+                        format!("{url} did not answer within {}s", self.timeout.as_secs());
+                    io::Error::new(ErrorKind::TimedOut, message).into()
+                }
+                Err(err) => err.into(),
+                Ok(response) => {
+                    let status = response.status();
+                    if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                        self.report(index, true);
+                        return Ok((response.error_for_status()?, url));
+                    }
+                    response.error_for_status().map(|_| ()).unwrap_err().into()
+                }
+            };
+            self.report(index, false);
+            last_error = Some(error);
+        }
+        Err(last_error.unwrap_or_else(|| eyre!("No IPFS gateway configured")))
+    }
+
+    /// Indices of the gateways in the order they are tried: as configured, those
+    /// failing recently last, soonest back first
+    fn order(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        let mut order: Vec<usize> = (0..self.gateways.len()).collect();
+        order.sort_by_key(|&index| health[index].benched_until.filter(|until| *until > now));
+        order
+    }
+
+    fn report(&self, index: usize, ok: bool) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[index];
+        if ok {
+            *health = Health::default();
+            return;
+        }
+        health.failures += 1;
+        let bench = BENCH_TIME * 2u32.pow(health.failures.min(6) - 1);
+        health.benched_until = Some(Instant::now() + bench.min(MAX_BENCH_TIME));
+    }
+}
+
+fn base58_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = vec![];
+    for c in text.chars() {
+        let mut carry = BASE58.find(c)? as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = text.chars().take_while(|c| *c == '1').count();
+    Some([vec![0; zeros], bytes].concat())
+}
+
+/// RFC 4648 base32, lowercase without padding
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(BASE32[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Whether `text` is a CIDv0, or a CIDv1 in one of the common multibase encodings
+pub fn is_cid(text: &str) -> bool {
+    let Some(encoded) = text.get(1..) else {
+        return false;
+    };
+    let all = |valid: fn(char) -> bool| encoded.chars().all(valid);
+    match text.chars().next() {
+        // CIDv0 is a bare base58btc sha2-256 multihash
+        Some('Q') => text.len() == 46 && text.starts_with("Qm") && all(|c| BASE58.contains(c)),
+        // CIDv1 by multibase prefix: base32, base32 upper, base58btc, base16 and base36
+        Some('b') => text.len() > 50 && all(|c| matches!(c, 'a'..='z' | '2'..='7')),
+        Some('B') => text.len() > 50 && all(|c| matches!(c, 'A'..='Z' | '2'..='7')),
+        Some('z') => text.len() > 40 && all(|c| BASE58.contains(c)),
+        Some('f') => text.len() > 60 && all(|c| matches!(c, '0'..='9' | 'a'..='f')),
+        Some('k') => text.len() > 50 && all(|c| matches!(c, '0'..='9' | 'a'..='z')),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const V1: &str = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+
+    #[test]
+    fn parses_url_spellings() {
+        for uri in [
+            format!("ipfs://{V0}/123.png"),
+            format!("ipfs://ipfs/{V0}/123.png"),
+            format!("IPFS:/ipfs/{V0}/123.png"),
+            format!("/ipfs/{V0}/123.png"),
+            format!("{V0}/123.png#frame"),
+        ] {
+            let path = IpfsPath::parse(&uri).unwrap();
+            assert_eq!(
+                path.gateway_url("https://ipfs.io/"),
+                format!("https://ipfs.io/ipfs/{V0}/123.png")
+            );
+        }
+
+        let v1 = IpfsPath::parse(&format!("ipfs://{V1}?filename=1.gif")).unwrap();
+        assert_eq!(v1.root, V1);
+        assert_eq!(v1.path, "?filename=1.gif");
+
+        let ipns = IpfsPath::parse("ipns://example.eth/1.json").unwrap();
+        assert_eq!(
+            ipns.gateway_url(DEFAULT_GATEWAY),
+            "https://ipfs.io/ipns/example.eth/1.json"
+        );
+    }
+
+    #[test]
+    fn rewrites_known_gateways() {
+        let pinata = format!("https://gateway.pinata.cloud/ipfs/{V0}/1.png");
+        let subdomain = format!("https://{V1}.ipfs.dweb.link/1.png");
+        for url in [pinata, subdomain] {
+            let content = IpfsPath::from_url(&url).unwrap();
+            assert_eq!(content.path, "/1.png");
+        }
+        assert!(IpfsPath::from_url(&format!("https://example.com/ipfs/{V0}")).is_none());
+    }
+
+    #[test]
+    fn builds_subdomain_urls() {
+        let gateway = Gateway::parse(FALLBACK_GATEWAY).unwrap();
+        let v1 = IpfsPath::parse(&format!("ipfs://{V1}/1.png")).unwrap();
+        assert_eq!(
+            gateway.url(&v1),
+            format!("https://{V1}.ipfs.dweb.link/1.png")
+        );
+
+        // CIDv0 are converted, subdomains being case-insensitive
+        let v0 = IpfsPath::parse(V0).unwrap().subdomain_label();
+        assert_eq!(
+            v0,
+            "bafybeie5nqv6kd3qnfjupgvz34woh3oksc3iau6abmyajn7qvtf6d2ho34"
+        );
+        assert!(Gateway::parse("https://{cid}.example.com").is_err());
+    }
+
+    #[test]
+    fn tries_failing_gateways_last() {
+        let pool = GatewayPool::new(vec![
+            Gateway::parse("https://one.example").unwrap(),
+            Gateway::parse("https://two.example").unwrap(),
+        ]);
+        assert_eq!(pool.order(), [0, 1]);
+        pool.report(0, false);
+        assert_eq!(pool.order(), [1, 0]);
+        pool.report(0, true);
+        assert_eq!(pool.order(), [0, 1]);
+    }
+
+    #[test]
+    fn rejects_other_paths() {
+        assert!(IpfsPath::parse("ipfs://not-a-cid/1.png").is_none());
+        assert!(IpfsPath::parse("images/1.png").is_none());
+        assert!(IpfsPath::parse("https://example.com/1.png").is_none());
+    }
+}
@@ -0,0 +1,63 @@
+//! Save an NFT collection to a local directory.
+//!
+//! The `nft-folder` binary is a thin consumer of this crate. Other front ends
+//! (such as the file manager plugins) can drive the same pipeline:
+//!
+//! ```no_run
+//! use ethers_providers::{Http, Provider};
+//! use nft_folder::{create_directory, Account, Downloader};
+//!
+//! # async fn run() -> eyre::Result<()> {
+//! let provider = Provider::<Http>::try_from("https://eth.llamarpc.com")?;
+//! let account = Account::resolve("name.eth", &provider).await?;
+//! let path = create_directory(account.folder(None)).await?;
+//!
+//! Downloader::new(reqwest::Client::new())
+//!     .max_concurrent(5)
+//!     .run(&account.address, path)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod account;
+mod chain;
+mod contract;
+pub mod data_uri;
+pub mod download;
+pub mod embed;
+pub mod graphql;
+pub mod ipfs;
+pub mod live;
+pub mod manifest;
+pub mod media;
+pub mod metadata;
+pub mod rasterize;
+pub mod rate_limit;
+pub mod request;
+pub mod retry;
+pub mod sidecar;
+pub mod source;
+mod state;
+pub mod sync;
+mod token;
+
+pub use account::{is_ens_name, resolve_ens_name, Account};
+pub use chain::Chain;
+pub use contract::{ContractReader, TokenUriReader};
+pub use download::{create_directory, handle_token, remove_partial_files, DownloadContext};
+pub use embed::EmbeddedMetadata;
+pub use ipfs::{Gateway, GatewayPool};
+pub use live::TransferWatcher;
+pub use manifest::{Manifest, ManifestEntry};
+pub use media::MediaSelection;
+pub use metadata::MetadataResolver;
+pub use rasterize::Rasterize;
+pub use request::{handle_processing, Downloader};
+pub use retry::{RetryPolicy, Retryable};
+pub use source::{
+    AlchemySource, NftSource, OnchainSource, OpenSeaSource, Page, SourceKind, ZoraRequest,
+};
+pub use state::STATE_FILE;
+pub use sync::{Removal, ARCHIVE_DIR};
+pub use token::{NftImage, NftToken, TokenStandard};
@@ -0,0 +1,357 @@
+use crate::chain::Chain;
+use crate::contract::ContractReader;
+use crate::manifest::Manifest;
+use crate::request::Downloader;
+use crate::source::onchain::{
+    transferred_tokens, OnchainSource, TokenRef, TRANSFER_BATCH_EVENT, TRANSFER_EVENT,
+    TRANSFER_SINGLE_EVENT,
+};
+use crate::sync::{remove_tokens, Removal};
+use console::style;
+use ethers::types::{Address, Filter, Log};
+use ethers_providers::{JsonRpcClient, Middleware, Provider, PubsubClient};
+use eyre::{eyre, Result};
+use futures::{stream, StreamExt};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Keeps a folder in step with the transfers of an address as they are mined.
+///
+/// ERC-721 and ERC-1155 transfers to and from the address are subscribed to over a
+/// pubsub (websocket or IPC) connection. Every token they move is read again from
+/// its contract: tokens still held are downloaded, the others archived or deleted.
+pub struct TransferWatcher<P> {
+    provider: Arc<Provider<P>>,
+    tokens: OnchainSource<Provider<P>>,
+    removal: Removal,
+}
+
+impl<P: PubsubClient + 'static> TransferWatcher<P> {
+    pub fn new(provider: Arc<Provider<P>>) -> Self {
+        TransferWatcher {
+            tokens: OnchainSource::new(Arc::new(ContractReader::new(Arc::clone(&provider)))),
+            provider,
+            removal: Removal::Archive,
+        }
+    }
+
+    /// What to do with the files of tokens sent away (default [`Removal::Archive`])
+    pub fn removal(mut self, removal: Removal) -> Self {
+        self.removal = removal;
+        self
+    }
+
+    /// Save the tokens of `address` with `downloader`, then follow its transfers
+    /// until the subscription closes.
+    pub async fn run(&self, downloader: &Downloader, address: &str, path: PathBuf) -> Result<()> {
+        let owner: Address = address
+            .parse()
+            .map_err(|err| eyre!("Invalid address {address}: {err}"))?;
+
+        let mut subscriptions = vec![];
+        for filter in &transfer_filters(owner) {
+            let logs = self
+                .provider
+                .subscribe_logs(filter)
+                .await
+                .map_err(|err| eyre!("Failed to subscribe to transfers: {err}"))?;
+            subscriptions.push(logs.boxed());
+        }
+        let mut logs = stream::select_all(subscriptions);
+
+        // Transfers mined meanwhile wait in the subscriptions
+        if let Err(err) = downloader.run(address, path.clone()).await {
+            println!("{} {err}", style("WARN").yellow());
+        }
+        println!(
+            "{} Following transfers of {address}",
+            style("INFO").bold().blue()
+        );
+
+        while let Some(log) = logs.next().await {
+            for token in moved_tokens(&log) {
+                if let Err(err) = self.update(downloader, owner, address, token, &path).await {
+                    println!("{} {err}", style("WARN").yellow());
+                }
+            }
+        }
+        Err(eyre!("Transfer subscription closed"))
+    }
+}
+
+impl<P: JsonRpcClient + 'static> TransferWatcher<P> {
+    /// Download `token` if `owner` holds it, otherwise remove its file
+    async fn update(
+        &self,
+        downloader: &Downloader,
+        owner: Address,
+        address: &str,
+        token: TokenRef,
+        path: &Path,
+    ) -> Result<()> {
+        if let Some(token) = self.tokens.current(owner, token).await? {
+            return downloader
+                .save(address, vec![token], path.to_path_buf())
+                .await;
+        }
+
+        let chain = self.tokens.chain().await?.unwrap_or(Chain::Ethereum);
+        let key = (chain, format!("{:?}", token.0), token.1.to_string());
+        let mut manifest = Manifest::load(path)?;
+        let removed = remove_tokens(&mut manifest, path, &HashSet::from([key]), self.removal)?;
+        if removed > 0 {
+            manifest.save(path)?;
+            println!(
+                "{} {} token {} of {:?}",
+                style("INFO").bold().blue(),
+                self.removal.verb(),
+                token.1,
+                token.0
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Subscriptions to the ERC-721 and ERC-1155 transfers from and to `owner`
+fn transfer_filters(owner: Address) -> [Filter; 4] {
+    // `to` and `from` are indexed in different positions for ERC-721 and ERC-1155
+    let erc1155 = [TRANSFER_SINGLE_EVENT, TRANSFER_BATCH_EVENT];
+    [
+        Filter::new().event(TRANSFER_EVENT).topic1(owner),
+        Filter::new().event(TRANSFER_EVENT).topic2(owner),
+        Filter::new().events(erc1155).topic2(owner),
+        Filter::new().events(erc1155).topic3(owner),
+    ]
+}
+
+/// Tokens moved by a subscribed `log`
+fn moved_tokens(log: &Log) -> Vec<TokenRef> {
+    // Logs of reorganized blocks are sent again with `removed` set
+    match log.removed {
+        Some(true) => vec![],
+        _ => transferred_tokens(log),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestEntry;
+    use crate::sync::ARCHIVE_DIR;
+    use crate::token::{NftToken, TokenStandard};
+    use ethers::abi::{encode, Token};
+    use ethers::types::{Bytes, ValueOrArray, H256, U256};
+    use ethers::utils::keccak256;
+    use ethers_providers::MockProvider;
+    use reqwest::Client;
+    use serde_json::{json, Value};
+    use std::fs;
+
+    fn owner() -> Address {
+        Address::repeat_byte(1)
+    }
+
+    fn other() -> Address {
+        Address::repeat_byte(2)
+    }
+
+    fn contract() -> Address {
+        Address::repeat_byte(0xaa)
+    }
+
+    fn log(event: &str, topics: &[Address], id: Option<u64>, data: Vec<u8>) -> Log {
+        let mut all = vec![H256::from(keccak256(event))];
+        all.extend(topics.iter().map(|&address| H256::from(address)));
+        all.extend(id.map(H256::from_low_u64_be));
+        Log {
+            address: contract(),
+            topics: all,
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    fn erc721(from: Address, to: Address, id: u64) -> Log {
+        log(TRANSFER_EVENT, &[from, to], Some(id), vec![])
+    }
+
+    fn erc1155(operator: Address, from: Address, to: Address, id: u64) -> Log {
+        let data = encode(&[Token::Uint(id.into()), Token::Uint(U256::one())]);
+        log(TRANSFER_SINGLE_EVENT, &[operator, from, to], None, data)
+    }
+
+    fn erc1155_batch(from: Address, to: Address, ids: &[u64]) -> Log {
+        let array = |values: Vec<Token>| Token::Array(values);
+        let data = encode(&[
+            array(ids.iter().map(|&id| Token::Uint(id.into())).collect()),
+            array(ids.iter().map(|_| Token::Uint(U256::one())).collect()),
+        ]);
+        log(TRANSFER_BATCH_EVENT, &[from, from, to], None, data)
+    }
+
+    /// Whether a node would send `log` to a subscription with `filter`
+    fn matches(filter: &Filter, log: &Log) -> bool {
+        filter.topics.iter().enumerate().all(|(index, topic)| {
+            let accepted: Vec<H256> = match topic {
+                None | Some(ValueOrArray::Value(None)) => return true,
+                Some(ValueOrArray::Value(Some(topic))) => vec![*topic],
+                Some(ValueOrArray::Array(topics)) => topics.iter().flatten().copied().collect(),
+            };
+            log.topics
+                .get(index)
+                .is_some_and(|topic| accepted.contains(topic))
+        })
+    }
+
+    #[test]
+    fn subscribes_to_transfers_both_ways() {
+        let filters = transfer_filters(owner());
+        let subscriptions = |log: &Log| filters.iter().filter(|f| matches(f, log)).count();
+        let (owner, other) = (owner(), other());
+
+        for log in [
+            erc721(other, owner, 7),
+            erc721(owner, other, 7),
+            erc1155(other, other, owner, 8),
+            erc1155(owner, owner, other, 8),
+            erc1155_batch(other, owner, &[9, 10]),
+            erc1155_batch(owner, other, &[9, 10]),
+        ] {
+            assert_eq!(subscriptions(&log), 1, "{log:?}");
+        }
+        assert_eq!(subscriptions(&erc721(other, other, 7)), 0);
+        // Operators moving the tokens of others
+        assert_eq!(subscriptions(&erc1155(owner, other, other, 8)), 0);
+    }
+
+    #[test]
+    fn skips_removed_logs() {
+        let mut log = erc721(other(), owner(), 7);
+        assert_eq!(
+            moved_tokens(&log),
+            [(contract(), U256::from(7), TokenStandard::Erc721)]
+        );
+        log.removed = Some(true);
+        assert!(moved_tokens(&log).is_empty());
+    }
+
+    /// Watcher whose RPC answers with `responses`, in the order requested
+    fn watcher(responses: Vec<Value>) -> TransferWatcher<MockProvider> {
+        let (provider, mock) = Provider::mocked();
+        // Responses are popped from the back
+        for response in responses.into_iter().rev() {
+            mock.push::<Value, _>(response).unwrap();
+        }
+        let provider = Arc::new(provider);
+        TransferWatcher {
+            tokens: OnchainSource::new(Arc::new(ContractReader::new(Arc::clone(&provider)))),
+            provider,
+            removal: Removal::Archive,
+        }
+    }
+
+    /// `eth_call` result returning `tokens`
+    fn returns(tokens: &[Token]) -> Value {
+        serde_json::to_value(Bytes::from(encode(tokens))).unwrap()
+    }
+
+    fn token(id: u64, standard: TokenStandard) -> TokenRef {
+        (contract(), U256::from(id), standard)
+    }
+
+    #[tokio::test]
+    async fn saves_tokens_received() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1"/>"#;
+        let uri = |name: &str| {
+            let image = format!("data:image/svg+xml;base64,{}", base64::encode(svg));
+            let metadata = json!({ "name": name, "image": image }).to_string();
+            format!("data:application/json;base64,{}", base64::encode(metadata))
+        };
+        let downloader = Downloader::new(Client::new());
+        let address = format!("{:?}", owner());
+
+        let erc721 = watcher(vec![
+            json!("0x1"),
+            returns(&[Token::Address(owner())]),
+            returns(&[Token::String(uri("Noun 7"))]),
+            returns(&[Token::String("Nouns".to_string())]),
+        ]);
+        let received = token(7, TokenStandard::Erc721);
+        erc721
+            .update(&downloader, owner(), &address, received, dir)
+            .await
+            .unwrap();
+
+        let erc1155 = watcher(vec![
+            json!("0x1"),
+            returns(&[Token::Uint(U256::from(2))]),
+            returns(&[Token::String(uri("Edition 8"))]),
+            returns(&[Token::String("Editions".to_string())]),
+        ]);
+        let received = token(8, TokenStandard::Erc1155);
+        erc1155
+            .update(&downloader, owner(), &address, received, dir)
+            .await
+            .unwrap();
+
+        let manifest = Manifest::load(dir).unwrap();
+        for (id, balance) in [("7", None), ("8", Some(2))] {
+            let (file, entry) = manifest
+                .files
+                .iter()
+                .find(|(_, entry)| entry.token_id.as_deref() == Some(id))
+                .unwrap();
+            assert!(dir.join(file).is_file());
+            assert_eq!(entry.balance, balance);
+        }
+    }
+
+    #[tokio::test]
+    async fn archives_tokens_sent_away() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut manifest = Manifest::default();
+        for (file, id, standard) in [
+            ("sold.png", 7, TokenStandard::Erc721),
+            ("edition.png", 8, TokenStandard::Erc1155),
+        ] {
+            fs::write(dir.join(file), id.to_string()).unwrap();
+            let saved = NftToken {
+                collection_address: Some(format!("{:?}", contract())),
+                token_id: Some(id.to_string()),
+                standard: Some(standard),
+                ..Default::default()
+            };
+            manifest.insert(file.to_string(), ManifestEntry::new(&saved));
+        }
+        manifest.save(dir).unwrap();
+        let downloader = Downloader::new(Client::new());
+        let address = format!("{:?}", owner());
+
+        let erc721 = watcher(vec![json!("0x1"), returns(&[Token::Address(other())])]);
+        let sent = token(7, TokenStandard::Erc721);
+        erc721
+            .update(&downloader, owner(), &address, sent, dir)
+            .await
+            .unwrap();
+        let erc1155 = watcher(vec![json!("0x1"), returns(&[Token::Uint(U256::zero())])]);
+        let sent = token(8, TokenStandard::Erc1155);
+        erc1155
+            .update(&downloader, owner(), &address, sent, dir)
+            .await
+            .unwrap();
+
+        let manifest = Manifest::load(dir).unwrap();
+        for file in ["sold.png", "edition.png"] {
+            assert!(!dir.join(file).exists());
+            assert!(dir.join(ARCHIVE_DIR).join(file).is_file());
+            assert!(manifest.get(&format!("{ARCHIVE_DIR}/{file}")).is_some());
+        }
+    }
+}
@@ -1,18 +1,23 @@
-mod download;
-mod request;
-
-use download::create_directory;
-use request::handle_processing;
-
 use ::core::time::Duration;
-use std::path::PathBuf;
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use console::style;
-use ethers::utils::hex::encode;
-use ethers_providers::{Http, Middleware, Provider};
+use ethers_providers::{Http, Provider, Ws};
 use eyre::Result;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use nft_folder::{
+    create_directory, is_ens_name, Account, Chain, Downloader, MediaSelection, NftSource,
+    SourceKind,
+};
+use nft_folder::{
+    rasterize::DEFAULT_RASTER_WIDTH, rate_limit::RateLimit, AlchemySource, OnchainSource,
+    OpenSeaSource, ZoraRequest,
+};
+use nft_folder::{
+    ContractReader, Gateway, GatewayPool, Rasterize, Removal, RetryPolicy, Retryable,
+    TransferWatcher,
+};
 use reqwest::Client;
+use std::{path::PathBuf, sync::Arc};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -26,6 +31,10 @@ struct Cli {
 enum Commands {
     /// Create a folder for the provided address
     Create(CreateArgs),
+    /// Update a folder to the tokens the address currently owns
+    Sync(SyncArgs),
+    /// Keep running, saving the tokens the address acquires as they appear
+    Watch(WatchArgs),
 }
 
 #[derive(Args)]
@@ -44,88 +53,289 @@ struct CreateArgs {
     /// RPC Url
     #[arg(long, default_value = "https://eth.llamarpc.com")]
     rpc: String,
+
+    /// API used to list the tokens owned by the address
+    #[arg(long, value_enum, default_value_t)]
+    source: SourceKind,
+
+    /// first block scanned for transfers (onchain source)
+    #[arg(long, default_value_t = 0)]
+    from_block: u64,
+
+    /// maximum blocks per eth_getLogs request (onchain source)
+    #[arg(long, default_value_t = 100_000)]
+    block_range: u64,
+
+    /// Alchemy API key (alchemy source)
+    #[arg(long, env = "ALCHEMY_API_KEY", hide_env_values = true)]
+    alchemy_key: Option<String>,
+
+    /// OpenSea API key (opensea source)
+    #[arg(long, env = "OPENSEA_API_KEY", hide_env_values = true)]
+    opensea_key: Option<String>,
+
+    /// keep tokens OpenSea flags as hidden or spam (opensea source)
+    #[arg(long)]
+    include_hidden: bool,
+
+    /// maximum requests per minute sent to the source API (zora default: 30)
+    #[arg(long, value_name = "REQUESTS")]
+    rate_limit: Option<u32>,
+
+    /// attempts made at each page request and download before giving up
+    #[arg(long, default_value_t = 5)]
+    max_attempts: u32,
+
+    /// wait in milliseconds before the first retry, doubled after each further failure
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    retry_backoff: u64,
+
+    /// also retry requests rejected with a 4xx status other than 429
+    #[arg(long)]
+    retry_client_errors: bool,
+
+    /// don't save each token's metadata as a .json file beside its media
+    #[arg(long)]
+    no_metadata: bool,
+
+    /// write each token's title, creator and links into its image file (XMP, PNG text)
+    #[arg(long)]
+    embed_metadata: bool,
+
+    /// media saved for each token: the image, the animation_url media (video, audio, 3D, HTML) or all
+    #[arg(long, value_enum, default_value_t)]
+    media: MediaSelection,
+
+    /// skip files larger than this many megabytes
+    #[arg(long, value_name = "MB")]
+    max_file_size: Option<u64>,
+
+    /// render SVG artwork to a PNG beside it, WIDTH pixels wide if given as --rasterize-svg=WIDTH
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, require_equals = true)]
+    rasterize_svg: Option<Option<u32>>,
+
+    /// save the rendered PNG in place of the SVG
+    #[arg(long, requires = "rasterize_svg")]
+    replace_svg: bool,
+
+    /// IPFS gateway, tried in the order given when the previous ones fail: https://ipfs.io (path style) or https://{cid}.ipfs.dweb.link (subdomain style). Repeatable
+    #[arg(long = "ipfs-gateway", value_name = "URL", value_parser = parse_gateway)]
+    ipfs_gateways: Vec<Gateway>,
+
+    /// seconds an IPFS gateway has to answer before the next one is tried
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    gateway_timeout: u64,
+
+    /// discard the progress saved by an interrupted run and start over
+    #[arg(long)]
+    restart: bool,
+
+    /// chain to save tokens from (zora, alchemy and opensea sources): ethereum, zora, base, optimism or all. Repeatable
+    #[arg(long = "chain", value_name = "CHAIN", value_parser = parse_chains, default_value = "ethereum")]
+    chains: Vec<ChainArg>,
 }
 
-struct Account {
-    name: Option<String>,
-    address: String,
+#[derive(Args)]
+struct SyncArgs {
+    #[command(flatten)]
+    create: CreateArgs,
+
+    /// delete the tokens no longer owned instead of moving them to _archive
+    #[arg(long)]
+    prune: bool,
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    #[command(flatten)]
+    create: CreateArgs,
+
+    /// seconds between two checks for new tokens
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    interval: u64,
+
+    /// websocket RPC (ws:// or wss://) to follow transfers live instead of polling
+    #[arg(long, value_name = "URL")]
+    ws: Option<String>,
+
+    /// delete the tokens sent away instead of moving them to _archive (with --ws)
+    #[arg(long, requires = "ws")]
+    prune: bool,
+}
+
+/// What `create` does once the folder is ready
+enum Mode {
+    Create,
+    Sync(Removal),
+    Watch(Duration),
+    Live { ws: String, removal: Removal },
+}
+
+/// One or all chains given to `--chain`
+#[derive(Clone)]
+struct ChainArg(Vec<Chain>);
+
+fn parse_chains(arg: &str) -> Result<ChainArg, String> {
+    if arg.eq_ignore_ascii_case("all") {
+        return Ok(ChainArg(Chain::ALL.to_vec()));
+    }
+    Chain::from_str(arg, true).map(|chain| ChainArg(vec![chain]))
+}
+
+fn parse_gateway(arg: &str) -> Result<Gateway, String> {
+    Gateway::parse(arg).map_err(|err| err.to_string())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     match cli.command {
-        Commands::Create(args) => {
-            let multi_pb = MultiProgress::new();
-            let provider = Provider::<Http>::try_from(args.rpc)?;
-            let account = match args.address {
-                arg if arg.split(".").last().unwrap() == "eth" => {
-                    let spinner =
-                        pending(&multi_pb, "ENS Detected. Resolving address...".to_string());
-                    let address = resolve_ens_name(&arg, provider).await?;
-                    spinner.finish_with_message(format!("Name Resolved to {address}"));
-                    Account {
-                        name: Some(arg.to_string()),
-                        address,
-                    }
-                }
-                arg if arg.starts_with("0x") => Account {
-                    name: None,
-                    address: arg.to_string(),
+        Commands::Create(args) => create(args, Mode::Create).await,
+        Commands::Sync(args) => {
+            let removal = Removal::from_prune(args.prune);
+            create(args.create, Mode::Sync(removal)).await
+        }
+        Commands::Watch(args) => {
+            let mode = match args.ws {
+                Some(ws) => Mode::Live {
+                    ws,
+                    removal: Removal::from_prune(args.prune),
                 },
-                _ => {
-                    return Err(eyre::eyre!(
-                        "{} Supported formats are 0xabc12... or name.eth",
-                        style("Invalid address").red()
-                    ))
-                }
+                None => Mode::Watch(Duration::from_secs(args.interval.max(1))),
             };
+            create(args.create, mode).await
+        }
+    }
+}
 
-            let mut path = args
-                .path
-                .map(PathBuf::from)
-                .or_else(|| dirs::picture_dir())
-                .unwrap_or_else(|| PathBuf::from("."));
-            path.push("nft-folder");
+/// Save the tokens owned by the address into its folder
+async fn create(args: CreateArgs, mode: Mode) -> Result<()> {
+    let multi_pb = MultiProgress::new();
+    let provider = Arc::new(Provider::<Http>::try_from(args.rpc)?);
+    let account = if is_ens_name(&args.address) {
+        let spinner = pending(&multi_pb, "ENS Detected. Resolving address...".to_string());
+        let account = Account::from_ens(&args.address, provider.as_ref()).await?;
+        spinner.finish_with_message(format!("Name Resolved to {}", account.address));
+        account
+    } else {
+        Account::from_hex(&args.address)?
+    };
 
-            path = match account.name {
-                Some(name) => path.join(name),
-                None => path.join(&account.address),
-            };
+    let path = account.folder(args.path);
+    let spinner = pending(
+        &multi_pb,
+        format!("Saving files to {}", path.to_string_lossy()),
+    );
+    let path = match create_directory(path).await {
+        Ok(path) => {
+            spinner.finish();
+            path
+        }
+        Err(err) => return Err(eyre::eyre!("{} {err}", style("Invalid Path").red())),
+    };
 
-            let spinner = pending(
-                &multi_pb,
-                format!("Saving files to {}", path.to_string_lossy()),
-            );
-            path = match create_directory(path).await {
-                Ok(path) => {
-                    spinner.finish();
-                    path
-                }
-                Err(err) => return Err(eyre::eyre!("{} {err}", style("Invalid Path").red())),
-            };
+    let mut chains: Vec<Chain> = vec![];
+    for chain in args.chains.into_iter().flat_map(|arg| arg.0) {
+        if !chains.contains(&chain) {
+            chains.push(chain);
+        }
+    }
 
-            let client = Client::new();
-            handle_processing(
-                &client,
-                account.address.as_str(),
-                path,
-                args.max_concurrent_downloads,
-            )
-            .await?;
-
-            /*
-               :: (4/6) Requesting NFT Data
-               :: (5/6) 45 NFTs found. Starting download
-            */
-            Ok(())
+    let rate_limit = args.rate_limit.map(RateLimit::per_minute);
+    let mut retry = RetryPolicy::default()
+        .max_attempts(args.max_attempts)
+        .backoff(
+            Duration::from_millis(args.retry_backoff),
+            Duration::from_secs(60),
+        );
+    if args.retry_client_errors {
+        retry.retryable.push(Retryable::ClientError);
+    }
+    let client = Client::new();
+    // One reader for the source and the metadata, so the chain id is requested once
+    let contracts = Arc::new(ContractReader::new(provider));
+    let source: Arc<dyn NftSource> = match args.source {
+        SourceKind::Zora => Arc::new(
+            ZoraRequest::new(client.clone())
+                .chains(chains)
+                .rate_limit(rate_limit.unwrap_or(ZoraRequest::RATE_LIMIT)),
+        ),
+        SourceKind::Onchain => Arc::new(
+            OnchainSource::new(Arc::clone(&contracts))
+                .from_block(args.from_block)
+                .block_range(args.block_range),
+        ),
+        SourceKind::Alchemy => {
+            let Some(key) = args.alchemy_key else {
+                return Err(eyre::eyre!(
+                    "{} Set --alchemy-key or ALCHEMY_API_KEY",
+                    style("Missing Alchemy API key").red()
+                ));
+            };
+            let mut alchemy = AlchemySource::new(client.clone(), key).chains(chains);
+            if let Some(limit) = rate_limit {
+                alchemy = alchemy.rate_limit(limit);
+            }
+            Arc::new(alchemy)
+        }
+        SourceKind::Opensea => {
+            let Some(key) = args.opensea_key else {
+                return Err(eyre::eyre!(
+                    "{} Set --opensea-key or OPENSEA_API_KEY",
+                    style("Missing OpenSea API key").red()
+                ));
+            };
+            let mut opensea = OpenSeaSource::new(client.clone(), key)
+                .chains(chains)
+                .include_hidden(args.include_hidden);
+            if let Some(limit) = rate_limit {
+                opensea = opensea.rate_limit(limit);
+            }
+            Arc::new(opensea)
+        }
+    };
+    let mut downloader = Downloader::new(client)
+        .source(source)
+        .contracts(contracts)
+        .max_concurrent(args.max_concurrent_downloads)
+        .page_retry(retry.clone())
+        .download_retry(retry)
+        .resume(!args.restart)
+        .sidecars(!args.no_metadata)
+        .embed_metadata(args.embed_metadata)
+        .media(args.media)
+        .ipfs_gateways(
+            GatewayPool::new(args.ipfs_gateways).timeout(Duration::from_secs(args.gateway_timeout)),
+        );
+    if let Some(megabytes) = args.max_file_size {
+        downloader = downloader.max_size(megabytes * 1_000_000);
+    }
+    if let Some(width) = args.rasterize_svg {
+        downloader = downloader.rasterize(Rasterize {
+            width: width.unwrap_or(DEFAULT_RASTER_WIDTH),
+            keep_svg: !args.replace_svg,
+        });
+    }
+    match mode {
+        Mode::Create => downloader.run(&account.address, path).await?,
+        Mode::Sync(removal) => downloader.sync(removal).run(&account.address, path).await?,
+        Mode::Watch(interval) => downloader.watch(&account.address, path, interval).await?,
+        Mode::Live { ws, removal } => {
+            let ws = Provider::<Ws>::connect(&ws)
+                .await
+                .map_err(|err| eyre::eyre!("{} {err}", style("Invalid websocket RPC").red()))?;
+            TransferWatcher::new(Arc::new(ws))
+                .removal(removal)
+                .run(&downloader, &account.address, path)
+                .await?
         }
     }
-}
 
-async fn resolve_ens_name(ens_name: &str, provider: Provider<Http>) -> Result<String> {
-    let address = provider.resolve_name(ens_name).await?;
-    Ok(format!("0x{}", encode(address)))
+    /*
+       :: (4/6) Requesting NFT Data
+       :: (5/6) 45 NFTs found. Starting download
+    */
+    Ok(())
 }
 
 /// Wrapsa generic action with a spinner then return it's result
@@ -0,0 +1,171 @@
+use crate::chain::Chain;
+use crate::token::{NftToken, TokenStandard};
+use ethers::utils::hex::encode;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::Path,
+    time::SystemTime,
+};
+
+/// Manifest location, relative to the account's folder
+pub const MANIFEST_FILE: &str = ".nft-folder/manifest.json";
+/// Format of the manifest written by this version
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Record of every file saved in a folder, mapping each back to its token.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    /// Entries by file path relative to the folder, `/` separated
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+/// A saved file and the token it belongs to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub chain: Option<Chain>,
+    /// Contract address of the collection
+    pub contract: Option<String>,
+    pub token_id: Option<String>,
+    pub standard: Option<TokenStandard>,
+    /// Editions held, for ERC-1155 tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<u64>,
+    pub name: Option<String>,
+    pub collection_name: Option<String>,
+    /// URL of the media as given by the token, `None` for media embedded in a data URI
+    pub source_url: Option<String>,
+    /// URL the file was downloaded from, after gateway substitution
+    pub url: Option<String>,
+    pub mime_type: Option<String>,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Hex SHA-256 of the file
+    pub sha256: String,
+    /// When the file was saved, in seconds since the Unix epoch
+    pub saved_at: u64,
+    /// Key of the metadata JSON saved beside the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<String>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Manifest of `dir`, empty if it has none yet
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = match fs::read(&path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Manifest::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let manifest: Manifest = serde_json::from_slice(&json)
+            .map_err(|err| eyre!("Invalid manifest {}: {err}", path.display()))?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(eyre!(
+                "{} was written by a newer version (format {})",
+                path.display(),
+                manifest.version
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest to `dir`, replacing the previous one at once
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Key of the file at `path` within `dir`
+    pub fn key(dir: &Path, path: &Path) -> String {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ManifestEntry> {
+        self.files.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, entry: ManifestEntry) {
+        self.files.insert(key, entry);
+    }
+}
+
+impl ManifestEntry {
+    /// Entry for `token`, before its file is written
+    pub fn new(token: &NftToken) -> Self {
+        ManifestEntry {
+            chain: token.chain,
+            contract: token.collection_address.clone(),
+            token_id: token.token_id.clone(),
+            standard: token.standard,
+            balance: token.balance,
+            name: token.name.clone(),
+            collection_name: token.collection_name.clone(),
+            source_url: None,
+            url: None,
+            mime_type: None,
+            size: 0,
+            sha256: String::new(),
+            saved_at: 0,
+            sidecar: None,
+        }
+    }
+
+    /// Record the size and hash of the written file, saved now
+    pub fn saved(mut self, size: u64, sha256: Sha256) -> Self {
+        self.size = size;
+        self.sha256 = encode(sha256.finalize());
+        self.saved_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self
+    }
+
+    /// Record the size and hash of a file already on disk
+    pub fn hash_file(self, path: &Path) -> Result<Self> {
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+        Ok(self.saved(size, hasher))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_relative_to_folder() {
+        let dir = Path::new("/tmp/nft-folder/name.eth");
+        assert_eq!(
+            Manifest::key(dir, &dir.join("base").join("Token #1.png")),
+            "base/Token #1.png"
+        );
+    }
+}
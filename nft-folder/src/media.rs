@@ -0,0 +1,145 @@
+/// Which of a token's media are saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MediaSelection {
+    /// The still image only
+    #[default]
+    Image,
+    /// The `animation_url` media (video, audio, 3D model or HTML), or the image without one
+    Animation,
+    /// Both the image and the animation
+    All,
+}
+
+impl MediaSelection {
+    /// URLs and MIME types to save among the token's `image` and `animation` URL,
+    /// flagged `true` for the animation
+    pub(crate) fn select(
+        &self,
+        image: Option<(String, Option<String>)>,
+        animation: Option<String>,
+    ) -> Vec<(String, Option<String>, bool)> {
+        let image = image.map(|(url, mime)| (url, mime, false));
+        let animation = animation.map(|url| (url, None, true));
+        match self {
+            MediaSelection::Image => image.into_iter().collect(),
+            MediaSelection::Animation => animation.or(image).into_iter().collect(),
+            MediaSelection::All => image.into_iter().chain(animation).collect(),
+        }
+    }
+}
+
+/// Extensions media can be saved with, to find a file saved by a previous run
+pub const EXTENSIONS: &[&str] = &[
+    "png", "jpg", "gif", "webp", "avif", "svg", "bmp", "mp4", "mov", "webm", "mp3", "wav", "ogg",
+    "glb", "gltf", "html",
+];
+
+/// Bytes of the start of a file [`sniff`] needs to tell its type
+pub const SNIFF_LENGTH: usize = 512;
+
+/// Extension of the file starting with `head`, from its magic bytes.
+///
+/// Metadata served in place of the media is reported as `json`.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    let brand = head.get(8..12);
+    let extension = match head {
+        [0x89, b'P', b'N', b'G', ..] => "png",
+        [0xff, 0xd8, 0xff, ..] => "jpg",
+        [b'G', b'I', b'F', b'8', ..] => "gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "webp",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "wav",
+        [b'B', b'M', ..] if head.len() > 14 => "bmp",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => match brand? {
+            b"avif" | b"avis" => "avif",
+            b"qt  " => "mov",
+            _ => "mp4",
+        },
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => "webm",
+        [b'O', b'g', b'g', b'S', ..] => "ogg",
+        [b'I', b'D', b'3', ..] | [0xff, 0xfb | 0xf3 | 0xf2, ..] => "mp3",
+        [b'g', b'l', b'T', b'F', ..] => "glb",
+        _ => return sniff_text(head),
+    };
+    Some(extension)
+}
+
+fn sniff_text(head: &[u8]) -> Option<&'static str> {
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    let lower = text.to_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        Some("html")
+    } else if lower.starts_with("<svg") || (lower.starts_with('<') && lower.contains("<svg")) {
+        Some("svg")
+    } else if text.starts_with('{') && text.contains("\"asset\"") {
+        Some("gltf")
+    } else if text.starts_with('{') || text.starts_with('[') {
+        Some("json")
+    } else {
+        None
+    }
+}
+
+/// Extension for the MIME type `mime`, ignoring parameters.
+///
+/// Generic types such as `application/octet-stream` have none.
+pub fn mime_extension(mime: &str) -> Option<&'static str> {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    let extension = match essence.to_lowercase().as_str() {
+        "image/png" => "png",
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/avif" => "avif",
+        "image/svg+xml" | "image/svg" => "svg",
+        "image/bmp" => "bmp",
+        "video/mp4" => "mp4",
+        "video/quicktime" => "mov",
+        "video/webm" => "webm",
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "audio/ogg" => "ogg",
+        "model/gltf-binary" => "glb",
+        "model/gltf+json" => "gltf",
+        "text/html" => "html",
+        "application/json" => "json",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_magic_bytes() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), Some("png"));
+        assert_eq!(sniff(b"\0\0\0\x20ftypisom\0\0\x02\0"), Some("mp4"));
+        assert_eq!(sniff(b"\0\0\0\x1cftypavif\0\0\0\0"), Some("avif"));
+        assert_eq!(sniff(b"glTF\x02\0\0\0"), Some("glb"));
+        assert_eq!(sniff(b"  <?xml version=\"1.0\"?>\n<svg>"), Some("svg"));
+        assert_eq!(sniff(b"{\"name\": \"Noun 1\"}"), Some("json"));
+        assert_eq!(sniff(b"plain"), None);
+    }
+
+    #[test]
+    fn selects_animation_over_image() {
+        let image = Some(("https://example.com/1.png".to_string(), None));
+        let animation = Some("https://example.com/1.mp4".to_string());
+        let selected = MediaSelection::Animation.select(image.clone(), animation.clone());
+        assert_eq!(selected, [(animation.clone().unwrap(), None, true)]);
+        assert_eq!(
+            MediaSelection::Animation.select(image.clone(), None).len(),
+            1
+        );
+        assert_eq!(MediaSelection::All.select(image, animation).len(), 2);
+    }
+
+    #[test]
+    fn maps_mime_types() {
+        assert_eq!(mime_extension("image/svg+xml"), Some("svg"));
+        assert_eq!(mime_extension("text/html; charset=utf-8"), Some("html"));
+        assert_eq!(mime_extension("application/octet-stream"), None);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::chain::Chain;
+use crate::contract::{ContractReader, TokenUriReader};
+use crate::data_uri::{is_data_uri, DataUri};
+use crate::ipfs::GatewayPool;
+use crate::token::{NftImage, NftToken};
+use base64::encode;
+use ethers::types::{Address, U256};
+use ethers_providers::Middleware;
+use eyre::{eyre, Result};
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Looks up a token's media in its metadata JSON when the source didn't return any.
+#[derive(Clone)]
+pub struct MetadataResolver {
+    client: Client,
+    contracts: Option<Arc<dyn TokenUriReader>>,
+    gateways: GatewayPool,
+}
+
+impl MetadataResolver {
+    pub fn new(client: Client) -> Self {
+        MetadataResolver {
+            client,
+            contracts: None,
+            gateways: GatewayPool::default(),
+        }
+    }
+
+    /// Fetch metadata on IPFS through `gateways`
+    pub fn gateways(mut self, gateways: GatewayPool) -> Self {
+        self.gateways = gateways;
+        self
+    }
+
+    /// Read token URIs the source didn't return from the contracts through `provider`,
+    /// for the tokens of the chain it is connected to
+    pub fn provider<M: Middleware + 'static>(self, provider: Arc<M>) -> Self {
+        self.contracts(Arc::new(ContractReader::new(provider)))
+    }
+
+    /// Read token URIs through `contracts`, see [`MetadataResolver::provider`]
+    pub fn contracts(mut self, contracts: Arc<dyn TokenUriReader>) -> Self {
+        self.contracts = Some(contracts);
+        self
+    }
+
+    /// Fill in `token.image` from the token's metadata.
+    ///
+    /// The name and raw metadata are filled in as well when the source didn't have them,
+    /// an image the source did return is kept.
+    pub async fn resolve(&self, token: &mut NftToken) -> Result<()> {
+        // Some sources return the metadata without extracting the media
+        if let Some(image) = token.metadata.as_ref().and_then(image_url) {
+            token.image = NftImage::Url(image);
+            return Ok(());
+        }
+
+        let uri = self.token_uri(token).await?;
+        let metadata = self.fetch(&uri).await?;
+        let image = image_url(&metadata);
+        if image.is_none() && matches!(token.image, NftImage::Null) {
+            return Err(eyre!("No image found in metadata at {uri}"));
+        }
+
+        if token.name.is_none() {
+            token.name = metadata
+                .get("name")
+                .and_then(Value::as_str)
+                .map(String::from);
+        }
+        if let (Some(image), NftImage::Null) = (image, &token.image) {
+            token.image = NftImage::Url(image);
+        }
+        token.token_url = Some(uri);
+        token.metadata.get_or_insert(metadata);
+        Ok(())
+    }
+
+    /// Token URI from the source, or read from the contract
+    async fn token_uri(&self, token: &NftToken) -> Result<String> {
+        if let Some(uri) = &token.token_url {
+            return Ok(uri.clone());
+        }
+        let (Some(contracts), Some(contract), Some(id)) =
+            (&self.contracts, &token.collection_address, &token.token_id)
+        else {
+            return Err(eyre!("No token URI available"));
+        };
+        // Reading another chain's contract would return another token's URI, if any
+        let chain = token.chain.unwrap_or(Chain::Ethereum);
+        if contracts.chain().await? != Some(chain) {
+            return Err(eyre!(
+                "No token URI available: the RPC provider is not on {chain}"
+            ));
+        }
+        let contract: Address = contract
+            .parse()
+            .map_err(|err| eyre!("Invalid contract address {contract}: {err}"))?;
+        let id = U256::from_dec_str(id).map_err(|err| eyre!("Invalid token id {id}: {err}"))?;
+        contracts.token_uri(contract, id, token.standard).await
+    }
+
+    /// Fetch the metadata JSON behind an http, ipfs, ar or data URI
+    pub async fn fetch(&self, uri: &str) -> Result<Value> {
+        if is_data_uri(uri) {
+            return DataUri::parse(uri)?.json();
+        }
+        let (response, _) = self.gateways.get(&self.client, uri).await?;
+        response
+            .json()
+            .await
+            .map_err(|err| eyre!("Invalid metadata at {uri}: {err}"))
+    }
+}
+
+/// Media URL from the `image`, `image_url` or `image_data` metadata fields.
+///
+/// `image_data` holds raw SVG markup, returned as a data URI.
+pub fn image_url(metadata: &Value) -> Option<String> {
+    let field = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|value| !value.is_empty())
+    };
+    if let Some(url) = field("image").or_else(|| field("image_url")) {
+        return Some(url.to_string());
+    }
+    field("image_data").map(|svg| match is_data_uri(svg) {
+        true => svg.to_string(),
+        false => format!("data:image/svg+xml;base64,{}", encode(svg)),
+    })
+}
+
+/// HTTP URL for `ar://` URIs, IPFS ones going through a [`GatewayPool`]
+pub(crate) fn gateway_url(uri: &str) -> String {
+    if let Some(path) = uri.strip_prefix("ar://") {
+        format!("https://arweave.net/{path}")
+    } else {
+        uri.to_string()
+    }
+}
@@ -0,0 +1,216 @@
+use crate::data_uri::{is_data_uri, DataUri};
+use crate::ipfs::GatewayPool;
+use base64::encode;
+use eyre::{eyre, Result};
+use futures::StreamExt;
+use reqwest::{header::CONTENT_TYPE, Client};
+use resvg::{tiny_skia, usvg};
+use std::{borrow::Cow, collections::HashMap, sync::OnceLock};
+
+/// Width of the PNGs rendered when none is given
+pub const DEFAULT_RASTER_WIDTH: u32 = 1024;
+
+/// How SVG artwork is turned into PNGs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rasterize {
+    /// Width of the PNG in pixels, the height follows the SVG's aspect ratio
+    pub width: u32,
+    /// Keep the SVG beside the PNG, rather than replacing it
+    pub keep_svg: bool,
+}
+
+impl Default for Rasterize {
+    fn default() -> Self {
+        Rasterize {
+            width: DEFAULT_RASTER_WIDTH,
+            keep_svg: true,
+        }
+    }
+}
+
+/// Render `svg` to a PNG `width` pixels wide.
+///
+/// Fonts embedded as data URLs are used along with the system ones. Images are
+/// only read from data URLs, see [`inline_references`] for the others.
+pub fn render(svg: &[u8], width: u32) -> Result<Vec<u8>> {
+    let fonts = embedded_fonts(&String::from_utf8_lossy(svg));
+    let fontdb = match fonts.is_empty() {
+        true => Cow::Borrowed(system_fonts()),
+        false => {
+            let mut fontdb = system_fonts().clone();
+            fonts
+                .into_iter()
+                .for_each(|font| fontdb.load_font_data(font));
+            Cow::Owned(fontdb)
+        }
+    };
+    let options = usvg::Options {
+        resources_dir: None,
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+            // No reading files from the disk on behalf of the artwork
+            resolve_string: Box::new(|_, _, _| None),
+        },
+        ..Default::default()
+    };
+    let tree =
+        usvg::Tree::from_data(svg, &options, &fontdb).map_err(|err| eyre!("Invalid SVG: {err}"))?;
+
+    let size = tree.size();
+    let scale = width as f32 / size.width();
+    let height = (size.height() * scale).round().max(1.0) as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| eyre!("Can't render a {width}x{height} image"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    pixmap
+        .encode_png()
+        .map_err(|err| eyre!("Failed to encode PNG: {err}"))
+}
+
+/// The system's fonts, loaded by the first render
+fn system_fonts() -> &'static usvg::fontdb::Database {
+    static FONTS: OnceLock<usvg::fontdb::Database> = OnceLock::new();
+    FONTS.get_or_init(|| {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        fontdb
+    })
+}
+
+/// `svg` with the images it links to over http, IPFS or Arweave replaced by data URLs.
+///
+/// Only `image/*` responses of at most `max_size` bytes are inlined, the
+/// references that fail are left out of the render.
+pub async fn inline_references(
+    client: &Client,
+    gateways: &GatewayPool,
+    svg: &str,
+    max_size: Option<u64>,
+) -> String {
+    let mut inlined: HashMap<&str, String> = HashMap::new();
+    for (start, end) in href_values(svg) {
+        let href = &svg[start..end];
+        let remote = ["http://", "https://", "ipfs://", "ar://"]
+            .iter()
+            .any(|scheme| href.starts_with(scheme));
+        if !remote || inlined.contains_key(href) {
+            continue;
+        }
+        let data = fetch_data_url(client, gateways, href, max_size)
+            .await
+            .unwrap_or_default();
+        inlined.insert(href, data);
+    }
+
+    let mut result = String::with_capacity(svg.len());
+    let mut last = 0;
+    for (start, end) in href_values(svg) {
+        if let Some(data) = inlined.get(&svg[start..end]) {
+            result.push_str(&svg[last..start]);
+            result.push_str(data);
+            last = end;
+        }
+    }
+    result.push_str(&svg[last..]);
+    result
+}
+
+async fn fetch_data_url(
+    client: &Client,
+    gateways: &GatewayPool,
+    url: &str,
+    max_size: Option<u64>,
+) -> Result<String> {
+    let (response, _) = gateways.get(client, url).await?;
+    let mime = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if !mime.starts_with("image/") {
+        return Err(eyre!("{url} is not an image: {mime}"));
+    }
+    let max_size = max_size.unwrap_or(u64::MAX);
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_size)
+    {
+        return Err(eyre!("{url} is over the {max_size} bytes limit"));
+    }
+    let mut bytes = vec![];
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() as u64 > max_size {
+            return Err(eyre!("{url} is over the {max_size} bytes limit"));
+        }
+    }
+    Ok(format!("data:{mime};base64,{}", encode(bytes)))
+}
+
+/// Byte ranges of the quoted values of the `href` and `xlink:href` attributes
+fn href_values(svg: &str) -> Vec<(usize, usize)> {
+    let mut values = vec![];
+    let mut offset = 0;
+    while let Some(found) = svg[offset..].find("href=") {
+        let quote_at = offset + found + "href=".len();
+        offset = quote_at;
+        let Some(quote) = svg[quote_at..]
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+        else {
+            continue;
+        };
+        let start = quote_at + 1;
+        let Some(length) = svg[start..].find(quote) else {
+            break;
+        };
+        values.push((start, start + length));
+        offset = start + length;
+    }
+    values
+}
+
+/// Fonts given as data URLs in the SVG's CSS, such as in `@font-face` rules
+fn embedded_fonts(svg: &str) -> Vec<Vec<u8>> {
+    svg.split("url(")
+        .skip(1)
+        .filter_map(|rest| {
+            let url = rest.split(')').next()?.trim().trim_matches(['"', '\'']);
+            is_data_uri(url).then(|| DataUri::parse(url).ok())?
+        })
+        .filter(|data| data.media_type.contains("font"))
+        .map(|data| data.data)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_references_and_fonts() {
+        let svg = r#"<svg><style>@font-face { src: url("data:font/ttf;base64,AAEAAA==") }</style>
+            <image xlink:href="ipfs://Qm/1.png"/><use href='#a'/></svg>"#;
+        let hrefs: Vec<&str> = href_values(svg)
+            .into_iter()
+            .map(|(start, end)| &svg[start..end])
+            .collect();
+        assert_eq!(hrefs, ["ipfs://Qm/1.png", "#a"]);
+        assert_eq!(embedded_fonts(svg), [vec![0, 1, 0, 0]]);
+    }
+
+    #[test]
+    fn renders_at_width() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="5"><rect width="10" height="5"/></svg>"#;
+        let png = render(svg, 20).unwrap();
+        // IHDR width and height
+        assert_eq!(png[16..24], [0, 0, 0, 20, 0, 0, 0, 10]);
+    }
+}
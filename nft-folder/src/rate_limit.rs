@@ -0,0 +1,183 @@
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Times a request rejected with `429 Too Many Requests` is sent again
+const MAX_RATE_LIMITED_RETRIES: u32 = 5;
+/// Wait after a `429` without a usable `Retry-After`
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Maximum number of requests sent to a host over a period
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub const fn per_minute(requests: u32) -> Self {
+        RateLimit {
+            requests,
+            per: Duration::from_secs(60),
+        }
+    }
+
+    pub const fn per_second(requests: u32) -> Self {
+        RateLimit {
+            requests,
+            per: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Async token bucket for the requests sent to one host.
+///
+/// Without a [`RateLimit`] requests go through immediately, but the host can
+/// still pause them by answering `429` with a `Retry-After`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: Option<RateLimit>,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        RateLimiter {
+            limit,
+            state: Mutex::new(Bucket {
+                tokens: limit.map_or(0.0, |limit| limit.requests as f64),
+                updated: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Wait until a request may be sent
+    pub async fn acquire(&self) {
+        while let Some(wait) = self.try_acquire() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take a token if one is available, otherwise how long to wait before trying again
+    fn try_acquire(&self) -> Option<Duration> {
+        let mut bucket = self.state.lock().unwrap();
+        let now = Instant::now();
+        if let Some(until) = bucket.paused_until {
+            if until > now {
+                return Some(until - now);
+            }
+            bucket.paused_until = None;
+        }
+
+        let limit = self.limit?;
+        let capacity = limit.requests.max(1) as f64;
+        let per_second = capacity / limit.per.as_secs_f64().max(f64::EPSILON);
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+
+    /// Hold every request to the host for `wait`
+    pub fn pause(&self, wait: Duration) {
+        let mut bucket = self.state.lock().unwrap();
+        let until = Instant::now() + wait;
+        bucket.paused_until = Some(
+            bucket
+                .paused_until
+                .map_or(until, |paused| paused.max(until)),
+        );
+        bucket.tokens = 0.0;
+    }
+}
+
+/// Limiters of every host requested during the run
+fn limiters() -> &'static Mutex<HashMap<String, Arc<RateLimiter>>> {
+    static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<RateLimiter>>>> = OnceLock::new();
+    LIMITERS.get_or_init(Default::default)
+}
+
+/// Limiter shared by all requests to `host`.
+///
+/// `limit` replaces the host's current limit when given, `None` keeps it.
+pub fn limiter(host: &str, limit: Option<RateLimit>) -> Arc<RateLimiter> {
+    let mut limiters = limiters().lock().unwrap();
+    match limiters.get(host) {
+        Some(limiter) if limit.is_none() || limiter.limit == limit => Arc::clone(limiter),
+        _ => {
+            let limiter = Arc::new(RateLimiter::new(limit));
+            limiters.insert(host.to_string(), Arc::clone(&limiter));
+            limiter
+        }
+    }
+}
+
+/// Send `request` once its host's limiter allows it.
+///
+/// `429` responses pause the host for their `Retry-After` before the request is sent again.
+pub async fn send(request: RequestBuilder, limit: Option<RateLimit>) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let limiter = limiter(request.url().host_str().unwrap_or_default(), limit);
+
+    let mut attempts = 0;
+    loop {
+        limiter.acquire().await;
+        // Streaming bodies can't be sent twice
+        let Some(retry) = request.try_clone() else {
+            return client.execute(request).await;
+        };
+        let response = client.execute(retry).await?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS
+            || attempts >= MAX_RATE_LIMITED_RETRIES
+        {
+            return Ok(response);
+        }
+        attempts += 1;
+        limiter.pause(retry_after(&response).unwrap_or(DEFAULT_RETRY_AFTER));
+    }
+}
+
+/// `Retry-After` header as a delay, given in seconds or as an HTTP date
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_once_burst_is_spent() {
+        let limiter = RateLimiter::new(Some(RateLimit::per_minute(2)));
+        assert_eq!(limiter.try_acquire(), None);
+        assert_eq!(limiter.try_acquire(), None);
+        let wait = limiter.try_acquire().unwrap();
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+    }
+}
@@ -1,233 +1,485 @@
-use crate::download::handle_token;
+use crate::chain::Chain;
+use crate::contract::TokenUriReader;
+use crate::download::{handle_token, remove_partial_files, DownloadContext};
+use crate::ipfs::GatewayPool;
+use crate::manifest::Manifest;
+use crate::media::MediaSelection;
+use crate::metadata::MetadataResolver;
+use crate::rasterize::Rasterize;
+use crate::retry::RetryPolicy;
+use crate::source::{NftSource, Page, ZoraRequest};
+use crate::state::Checkpoint;
+use crate::sync::{reconcile, saved_tokens, token_key, Removal, TokenKey};
+use crate::token::{NftImage, NftToken};
+use async_trait::async_trait;
+use console::style;
+use ethers_providers::Middleware;
 use eyre::{eyre, Report, Result};
-use futures::{stream, StreamExt};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::{Client, Response};
-use serde::{Deserialize, Serialize};
-use serde_json::to_value;
-use std::{path::PathBuf, sync::Arc};
-use tokio::{sync::Semaphore, task::JoinSet};
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-#[serde(rename_all = "camelCase")]
-pub enum NftImage {
-    Null,
-    Url(String),
-    Object {
-        url: String,
-        size: Option<serde_json::Value>,
-        mime_type: Option<String>,
-    },
-}
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct NftToken {
-    pub image: NftImage,
-    pub name: Option<String>,
-    pub collection_name: Option<String>,
-    pub token_url: Option<String>,
-    pub token_id: Option<String>,
-    pub metadata: Option<serde_json::Value>,
-}
-#[derive(Serialize, Deserialize, Debug)]
-pub struct NftNode {
-    pub token: NftToken,
-}
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct PageInfo {
-    pub end_cursor: Option<String>,
-    pub has_next_page: bool,
-    limit: i32,
-}
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct NftNodes {
-    pub nodes: Vec<NftNode>,
-    pub page_info: PageInfo,
-}
-#[derive(Serialize, Deserialize, Debug)]
-pub struct NftData {
-    pub tokens: NftNodes,
-}
-#[derive(Deserialize, Serialize, Debug)]
-pub struct FailedRequest {
-    message: String,
-    locations: Vec<ErrorLocation>,
-    path: Vec<String>,
-}
-#[derive(Deserialize, Serialize, Debug)]
-struct ErrorLocation {
-    line: u64,
-    column: u64,
+use reqwest::Client;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::Semaphore,
+    task::{JoinHandle, JoinSet},
+};
+
+/// Download every token owned by `address` into `path`, at most `max` at a time.
+///
+/// Shorthand for [`Downloader::new`] with [`Downloader::max_concurrent`].
+pub async fn handle_processing(
+    client: &Client,
+    address: &str,
+    path: PathBuf,
+    max: usize,
+) -> eyre::Result<()> {
+    Downloader::new(client.clone())
+        .max_concurrent(max)
+        .run(address, path)
+        .await
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ZoraRequest {
-    data: Option<NftData>,
-    error: Option<FailedRequest>,
+/// Orchestrates fetching an account's tokens and saving them to a folder.
+#[derive(Clone)]
+pub struct Downloader {
+    client: Client,
+    source: Arc<dyn NftSource>,
+    metadata: MetadataResolver,
+    max_concurrent: usize,
+    page_retry: RetryPolicy,
+    download_retry: RetryPolicy,
+    resume: bool,
+    sync: Option<Removal>,
+    skip_saved: bool,
+    sidecars: bool,
+    embed: bool,
+    media: MediaSelection,
+    max_size: Option<u64>,
+    rasterize: Option<Rasterize>,
+    gateways: GatewayPool,
+    /// Keep the folder's run state, see [`Checkpoint`]
+    checkpoint: bool,
 }
 
-impl ZoraRequest {
-    const API: &'static str = "https://api.zora.co/graphql";
-
-    async fn send(
-        client: &Client,
-        cursor: Option<String>,
-        address: &str,
-    ) -> Result<Response, reqwest::Error> {
-        let cursor = match cursor {
-            Some(c) => format!(r#", after: "{}""#, c),
-            None => "".to_owned(),
-        };
+impl Downloader {
+    /// Downloader listing tokens through Zora's public API
+    pub fn new(client: Client) -> Self {
+        Downloader {
+            source: Arc::new(ZoraRequest::new(client.clone())),
+            metadata: MetadataResolver::new(client.clone()),
+            client,
+            max_concurrent: 5,
+            page_retry: RetryPolicy::default(),
+            download_retry: RetryPolicy::default(),
+            resume: true,
+            sync: None,
+            skip_saved: false,
+            sidecars: true,
+            embed: false,
+            media: MediaSelection::default(),
+            max_size: None,
+            rasterize: None,
+            gateways: GatewayPool::default(),
+            checkpoint: true,
+        }
+    }
 
-        let query = format!(
-            r#"
-            query NFTsForAddress {{
-                tokens(networks: [{{network: ETHEREUM, chain: MAINNET}}],
-                    pagination: {{limit: 200 {} }},
-                    where: {{ownerAddresses: "{}"}}) {{
-                        nodes {{
-                            token {{
-                                tokenId
-                                    tokenUrl
-                                    collectionName
-                                    name
-                                    image {{
-                                        url
-                                        size
-                                        mimeType
-                                    }}
-                            }}
-                        }}
-                        pageInfo {{
-                            endCursor
-                            hasNextPage
-                            limit
-                        }}
-                    }}
-                }}
-            "#,
-            cursor, address
-        );
+    /// Source used to list the account's tokens
+    pub fn source(mut self, source: Arc<dyn NftSource>) -> Self {
+        self.source = source;
+        self
+    }
 
-        let request_body = to_value(serde_json::json!({
-            "query": query,
-            "variables": null,
-        }))
-        .unwrap();
+    /// RPC provider used to read token URIs from the contracts when the source has no media
+    pub fn provider<M: Middleware + 'static>(mut self, provider: Arc<M>) -> Self {
+        self.metadata = self.metadata.provider(provider);
+        self
+    }
 
-        client
-            .post(ZoraRequest::API)
-            .json(&request_body)
-            .send()
-            .await
+    /// Contract reader used instead of [`Downloader::provider`]'s, to share it with the source
+    pub fn contracts(mut self, contracts: Arc<dyn TokenUriReader>) -> Self {
+        self.metadata = self.metadata.contracts(contracts);
+        self
     }
-}
 
-pub async fn fetch_page(
-    client: &Client,
-    cursor: Option<String>,
-    address: &str,
-) -> Result<Option<NftNodes>> {
-    let response = ZoraRequest::send(client, cursor, address)
-        .await
-        .map_err(|err| eyre!("Failed to send request: {}", err))?;
-    let mut response_body = response.bytes_stream();
+    /// Maximum number of parallel downloads (default 5)
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        self.max_concurrent = max;
+        self
+    }
 
-    let mut response_data = Vec::new();
-    while let Some(item) = StreamExt::next(&mut response_body).await {
-        let chunk = item.map_err(|err| eyre!("Failed to read response: {}", err))?;
-        response_data.extend_from_slice(&chunk);
+    /// Retries of the source's page requests (default [`RetryPolicy::default`])
+    pub fn page_retry(mut self, policy: RetryPolicy) -> Self {
+        self.page_retry = policy;
+        self
     }
 
-    let response_str = String::from_utf8(response_data)
-        .map_err(|err| eyre!("Failed to convert response to string: {}", err))?;
+    /// Retries of media downloads (default [`RetryPolicy::default`])
+    pub fn download_retry(mut self, policy: RetryPolicy) -> Self {
+        self.download_retry = policy;
+        self
+    }
 
-    let response: ZoraRequest = serde_json::from_str(&response_str)
-        .map_err(|err| eyre!("Failed to parse JSON response: {}", err))?;
+    /// Continue the run previously interrupted in the folder, if any (default true).
+    ///
+    /// When unset the saved state is discarded and every page is requested again.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
 
-    if let Some(data) = response.data {
-        Ok(Some(data.tokens))
-    } else if let Some(error) = response.error {
-        Err(eyre!("Errors: {:?}", error))
-    } else {
-        Ok(None)
+    /// Also remove the files of tokens the account no longer owns, once every page was listed.
+    ///
+    /// Syncing always pages through the whole source, ignoring [`Downloader::resume`].
+    pub fn sync(mut self, removal: Removal) -> Self {
+        self.sync = Some(removal);
+        self
     }
-}
 
-pub async fn handle_processing(
-    client: &Client,
-    address: &str,
-    path: PathBuf,
-    max: usize,
-) -> eyre::Result<()> {
-    let cursor = None;
-    let requests = stream::unfold(cursor, move |cursor| async move {
-        match fetch_page(&client, cursor, address).await {
-            Ok(Some(response)) => {
-                if !response.nodes.is_empty() {
-                    let items = stream::iter(response.nodes.into_iter().map(|node| node.token));
-                    let next_cursor = response.page_info.end_cursor;
-                    // Max 30 requests per min to public Zora API, but doesn't kick in below 6000 (30*200) tokens
-                    // std::thread::sleep(std::time::Duration::from_millis(2000));
-                    Some((items, next_cursor))
-                } else {
-                    None
-                }
-            }
-            Ok(None) => None,
-            Err(err) => {
-                println!("Error fetching data: {}", err);
-                None
+    /// Write each token's metadata as `{name}.json` beside its media (default true)
+    pub fn sidecars(mut self, sidecars: bool) -> Self {
+        self.sidecars = sidecars;
+        self
+    }
+
+    /// Embed each token's title, description, creator and links into its image file:
+    /// XMP for JPEG, WebP and SVG, text chunks for PNG (default false)
+    pub fn embed_metadata(mut self, embed: bool) -> Self {
+        self.embed = embed;
+        self
+    }
+
+    /// Which of each token's media to save (default [`MediaSelection::Image`]).
+    ///
+    /// Tokens listed without metadata have it fetched to find their `animation_url`.
+    pub fn media(mut self, media: MediaSelection) -> Self {
+        self.media = media;
+        self
+    }
+
+    /// Abandon downloads larger than `bytes` (default unlimited)
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Render SVG artwork to PNG, beside the SVG or in its place (default off).
+    ///
+    /// SVGs saved by earlier runs are rendered as they are found.
+    pub fn rasterize(mut self, rasterize: Rasterize) -> Self {
+        self.rasterize = Some(rasterize);
+        self
+    }
+
+    /// Gateways media and metadata on IPFS are fetched through, in order of preference
+    /// (default ipfs.io, then dweb.link).
+    ///
+    /// Links to well-known public gateways are fetched through these as well.
+    pub fn ipfs_gateways(mut self, gateways: GatewayPool) -> Self {
+        self.metadata = self.metadata.gateways(gateways.clone());
+        self.gateways = gateways;
+        self
+    }
+
+    /// Pass over the tokens the folder's manifest already has a file for, without
+    /// showing or checking them (default false)
+    pub fn skip_saved(mut self, skip: bool) -> Self {
+        self.skip_saved = skip;
+        self
+    }
+
+    /// Save the tokens of `address` into `path`, then poll the source every `interval`
+    /// and download the tokens acquired since, until the task is dropped.
+    ///
+    /// Failed polls are reported and tried again at the next interval.
+    pub async fn watch(&self, address: &str, path: PathBuf, interval: Duration) -> Result<()> {
+        let downloader = self.clone().skip_saved(true);
+        loop {
+            if let Err(err) = downloader.run(address, path.clone()).await {
+                println!("{} {err}", style("WARN").yellow());
             }
+            println!(
+                "{} Checking for new tokens again in {}s",
+                style("INFO").bold().blue(),
+                interval.as_secs()
+            );
+            tokio::time::sleep(interval).await;
         }
-    })
-    .flatten();
-    tokio::pin!(requests);
-
-    let mp = MultiProgress::new();
-    mp.set_alignment(indicatif::MultiProgressAlignment::Bottom);
-    let total_pb = mp.add(ProgressBar::new(0));
-    total_pb.set_style(
-        ProgressStyle::with_template("Found: {len:>3.bold.blue}  Saved: {pos:>3.bold.blue} {msg}")
+    }
+
+    /// Save `tokens` of `address` into `path`, as if they were the only ones listed
+    pub async fn save(&self, address: &str, tokens: Vec<NftToken>, path: PathBuf) -> Result<()> {
+        let mut downloader = self.clone().source(Arc::new(Listed(Mutex::new(tokens))));
+        downloader.sync = None;
+        // The run these tokens arrive during keeps its own progress
+        downloader.checkpoint = false;
+        downloader.resume(false).run(address, path).await
+    }
+
+    /// Page through every token owned by `address` and save it into `path`.
+    ///
+    /// Progress is saved in the folder as it goes, see [`Downloader::resume`].
+    /// Fails once the saved tokens are settled if the source could not be paged through.
+    pub async fn run(&self, address: &str, path: PathBuf) -> eyre::Result<()> {
+        let context = DownloadContext {
+            client: self.client.clone(),
+            retry: self.download_retry.clone(),
+            manifest: Arc::new(Mutex::new(Manifest::load(&path)?)),
+            sidecars: self.sidecars,
+            embed: self.embed,
+            media: self.media,
+            max_size: self.max_size,
+            rasterize: self.rasterize,
+            gateways: self.gateways.clone(),
+        };
+
+        let mp = MultiProgress::new();
+        mp.set_alignment(indicatif::MultiProgressAlignment::Bottom);
+        let total_pb = mp.add(ProgressBar::new(0));
+        total_pb.set_style(
+            ProgressStyle::with_template(
+                "Found: {len:>3.bold.blue}  Saved: {pos:>3.bold.blue} {msg}",
+            )
             .unwrap(),
-    );
+        );
 
-    let semaphore = Arc::new(Semaphore::new(max));
-    let mut errors: Vec<Report> = vec![];
-    let mut set = JoinSet::new();
+        let semaphore = Arc::new(Semaphore::new(self.max_concurrent));
+        let mut errors: Vec<Report> = vec![];
+        let mut set = JoinSet::new();
 
-    while let Some(token) = requests.next().await {
-        total_pb.inc_length(1);
-        match handle_token(Arc::clone(&semaphore), token, &client, &mp, &path) {
-            Ok(Some(task)) => {
-                set.spawn(task);
-            }
-            Ok(None) => total_pb.inc(1),
-            Err(err) => errors.push(err),
+        remove_partial_files(&path)?;
+        let resume = self.resume && self.sync.is_none();
+        let (checkpoint, unsaved) = match self.checkpoint {
+            true => Checkpoint::open(&path, address, resume)?,
+            false => (Checkpoint::detached(address), vec![]),
+        };
+        let mut cursor = checkpoint.cursor();
+        if !unsaved.is_empty() || cursor != Some(None) {
+            mp.println(format!(
+                "{} Resuming previous run with {} unsaved tokens{}",
+                style("INFO").bold().blue(),
+                unsaved.len(),
+                match cursor {
+                    Some(Some(_)) => ", then the remaining pages",
+                    _ => "",
+                }
+            ))?;
         }
-    }
+        let checkpoint = Arc::new(Mutex::new(checkpoint));
 
-    while let Some(tasks) = set.join_next().await {
-        let tasks = tasks.unwrap();
-        match tasks.unwrap() {
-            Ok(_) => {
+        let saved = match self.skip_saved {
+            true => saved_tokens(&context.manifest.lock().unwrap()),
+            false => HashSet::new(),
+        };
+        let mut owned = HashSet::new();
+        let mut queue = |token: NftToken| {
+            let key = token_key(&token);
+            // Tokens queued again from the previous run are listed again as well
+            if key.as_ref().is_some_and(|key| owned.contains(key)) {
+                return;
+            }
+            total_pb.inc_length(1);
+            let already_saved = key.as_ref().is_some_and(|key| saved.contains(key));
+            owned.extend(key);
+            if already_saved {
                 total_pb.inc(1);
+                return;
+            }
+            let id = checkpoint.lock().unwrap().queue(&token);
+            let unresolved = match token.image {
+                NftImage::Null => true,
+                _ => self.media != MediaSelection::Image && token.metadata.is_none(),
+            };
+            let task = match unresolved {
+                true => Ok(Some(self.resolve_token(
+                    Arc::clone(&semaphore),
+                    token,
+                    &context,
+                    &mp,
+                    &path,
+                ))),
+                false => handle_token(Arc::clone(&semaphore), token, &context, &mp, &path),
+            };
+            match task {
+                Ok(Some(task)) => {
+                    let checkpoint = Arc::clone(&checkpoint);
+                    set.spawn(async move {
+                        let result = task.await;
+                        let saved = matches!(result, Ok(Ok(())));
+                        checkpoint.lock().unwrap().settle(id, saved);
+                        result
+                    });
+                }
+                Ok(None) => {
+                    checkpoint.lock().unwrap().settle(id, true);
+                    total_pb.inc(1);
+                }
+                Err(err) => {
+                    checkpoint.lock().unwrap().settle(id, false);
+                    errors.push(err);
+                }
             }
-            Err(err) => {
-                errors.push(err);
+        };
+
+        unsaved.into_iter().for_each(&mut queue);
+
+        let mut fetch_error = None;
+        while let Some(page_cursor) = cursor {
+            let max_attempts = self.page_retry.max_attempts;
+            let mut attempts = 0;
+            let page = self
+                .page_retry
+                .run(|attempt| {
+                    attempts = attempt;
+                    if attempt > 1 {
+                        total_pb.set_message(format!("Retrying page ({attempt}/{max_attempts})"));
+                    }
+                    self.source.fetch_page(address, page_cursor.clone())
+                })
+                .await;
+            total_pb.set_message("");
+            let page = match page {
+                Ok(page) => page,
+                Err(err) => {
+                    fetch_error = Some(match attempts {
+                        1 => eyre!("Error fetching data: {err}"),
+                        attempts => eyre!("Error fetching data after {attempts} attempts: {err}"),
+                    });
+                    break;
+                }
+            };
+
+            for warning in &page.warnings {
+                mp.println(format!("{} {warning}", style("WARN").yellow()))?;
             }
+            page.tokens.into_iter().for_each(&mut queue);
+            checkpoint
+                .lock()
+                .unwrap()
+                .page_done(page.next_cursor.clone())?;
+            context.manifest.lock().unwrap().save(&path)?;
+            cursor = page.next_cursor.map(Some);
         }
+
+        while let Some(tasks) = set.join_next().await {
+            let tasks = tasks.unwrap();
+            match tasks.unwrap() {
+                Ok(_) => {
+                    total_pb.inc(1);
+                }
+                Err(err) => {
+                    errors.push(err);
+                }
+            }
+        }
+
+        if errors.is_empty() && fetch_error.is_none() {
+            total_pb.finish_with_message("Completed all sucessfully");
+        } else {
+            total_pb.abandon();
+            errors.iter().for_each(|e| println!("{}", e))
+        }
+
+        if let Some(removal) = self.sync {
+            self.remove_unowned(&context, &path, &owned, removal, fetch_error.is_some())?;
+        }
+
+        checkpoint.lock().unwrap().finish()?;
+        context.manifest.lock().unwrap().save(&path)?;
+        match fetch_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Archive or delete the files of tokens missing from `owned`, unless the listing is `incomplete`
+    fn remove_unowned(
+        &self,
+        context: &DownloadContext,
+        path: &Path,
+        owned: &HashSet<TokenKey>,
+        removal: Removal,
+        incomplete: bool,
+    ) -> Result<()> {
+        if incomplete {
+            println!(
+                "{} Kept the tokens no longer owned, not every page could be listed",
+                style("WARN").yellow()
+            );
+            return Ok(());
+        }
+        let chains: HashSet<Chain> = match self.source.chains() {
+            Some(chains) => chains.into_iter().collect(),
+            None => owned.iter().map(|token| token.0).collect(),
+        };
+        let mut manifest = context.manifest.lock().unwrap();
+        let removed = reconcile(&mut manifest, path, owned, &chains, removal)?;
+        if removed > 0 {
+            println!(
+                "{} {} {removed} tokens no longer owned",
+                style("INFO").bold().blue(),
+                removal.verb()
+            );
+        }
+        Ok(())
     }
 
-    if errors.is_empty() {
-        total_pb.finish_with_message("Completed all sucessfully");
-    } else {
-        total_pb.abandon();
-        errors.iter().for_each(|e| println!("{}", e))
+    /// Find the media of a token in its metadata, then download it
+    fn resolve_token(
+        &self,
+        semaphore: Arc<Semaphore>,
+        mut token: NftToken,
+        context: &DownloadContext,
+        mp: &MultiProgress,
+        path: &Path,
+    ) -> JoinHandle<Result<()>> {
+        let (metadata, context) = (self.metadata.clone(), context.clone());
+        let (mp, path) = (mp.clone(), path.to_path_buf());
+        tokio::spawn(async move {
+            let permit = semaphore.acquire().await.unwrap();
+            let resolved = metadata.resolve(&mut token).await;
+            drop(permit);
+            let name = token.display_name().unwrap_or_default();
+            match (resolved, &token.image) {
+                (Ok(()), NftImage::Null) => {
+                    return Err(eyre!("No image URL found for {name}: No image in metadata"))
+                }
+                (Err(err), NftImage::Null) => {
+                    return Err(eyre!("No image URL found for {name}: {err}"))
+                }
+                // Without metadata, the image listed by the source is still saved
+                (Err(err), _) => {
+                    mp.println(format!(
+                        "{} No metadata for {name}, saving its image only: {err}",
+                        style("WARN").yellow()
+                    ))
+                    .ok();
+                }
+                (Ok(()), _) => {}
+            }
+
+            match handle_token(semaphore, token, &context, &mp, &path)? {
+                Some(task) => task.await?,
+                None => Ok(()),
+            }
+        })
     }
+}
+
+/// Source listing a fixed set of tokens as a single page
+struct Listed(Mutex<Vec<NftToken>>);
 
-    Ok(())
+#[async_trait]
+impl NftSource for Listed {
+    async fn fetch_page(&self, _owner: &str, _cursor: Option<String>) -> Result<Page> {
+        Ok(Page {
+            tokens: std::mem::take(&mut *self.0.lock().unwrap()),
+            next_cursor: None,
+            warnings: vec![],
+        })
+    }
 }
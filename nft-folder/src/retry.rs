@@ -0,0 +1,265 @@
+use crate::graphql::GraphQlClientError;
+use ethers_providers::{HttpClientError, ProviderError};
+use eyre::{Report, Result};
+use reqwest::StatusCode;
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io::{self, ErrorKind},
+    time::{Duration, SystemTime},
+};
+
+/// Kinds of failure a [`RetryPolicy`] can retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retryable {
+    /// Connection failures, timeouts and bodies interrupted mid-transfer
+    Transport,
+    /// `429 Too Many Requests` still returned once the rate limiter gave up
+    TooManyRequests,
+    /// Any other `4xx` status
+    ClientError,
+    /// `5xx` statuses
+    ServerError,
+}
+
+impl Retryable {
+    pub fn from_status(status: StatusCode) -> Option<Self> {
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            Some(Retryable::TooManyRequests)
+        } else if status.is_client_error() {
+            Some(Retryable::ClientError)
+        } else if status.is_server_error() {
+            Some(Retryable::ServerError)
+        } else {
+            None
+        }
+    }
+
+    fn from_reqwest(err: &reqwest::Error) -> Option<Self> {
+        match err.status() {
+            Some(status) => Retryable::from_status(status),
+            None if err.is_connect() || err.is_timeout() || err.is_request() || err.is_body() => {
+                Some(Retryable::Transport)
+            }
+            None => None,
+        }
+    }
+
+    fn from_provider(err: &ProviderError) -> Option<Self> {
+        let ProviderError::JsonRpcClientError(err) = err else {
+            return match err {
+                ProviderError::HTTPError(err) => Retryable::from_reqwest(err),
+                _ => None,
+            };
+        };
+        let transport: &(dyn std::error::Error + 'static) = &**err;
+        if let Some(HttpClientError::ReqwestError(err)) = transport.downcast_ref() {
+            return Retryable::from_reqwest(err);
+        }
+        // The node answered, with an error or a malformed response, otherwise the
+        // connection failed
+        match err.as_error_response().is_some() || err.as_serde_error().is_some() {
+            true => None,
+            false => Some(Retryable::Transport),
+        }
+    }
+
+    /// Kind of `err`, `None` when it can't be told apart from a permanent failure
+    pub fn classify(err: &Report) -> Option<Self> {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                return Retryable::from_reqwest(err);
+            }
+            if let Some(err) = cause.downcast_ref::<ProviderError>() {
+                return Retryable::from_provider(err);
+            }
+            // Bodies shorter than their `Content-Length` and unanswered requests
+            if let Some(err) = cause.downcast_ref::<io::Error>() {
+                let transport =
+                    matches!(err.kind(), ErrorKind::UnexpectedEof | ErrorKind::TimedOut);
+                return transport.then_some(Retryable::Transport);
+            }
+            match cause.downcast_ref::<GraphQlClientError>() {
+                Some(GraphQlClientError::Transport(err)) => return Retryable::from_reqwest(err),
+                Some(GraphQlClientError::Status { status, .. }) => {
+                    return Retryable::from_status(*status)
+                }
+                Some(_) => return None,
+                None => {}
+            }
+        }
+        None
+    }
+}
+
+/// How often and how patiently a failed request is sent again.
+///
+/// The wait before attempt `n + 1` is `initial_backoff * multiplier^(n - 1)`, capped
+/// at `max_backoff`, then shortened by up to `jitter` of itself at random.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts made in total, including the first one
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Fraction of each wait randomized, from 0 to 1
+    pub jitter: f64,
+    /// Failures worth another attempt. Anything else fails immediately.
+    pub retryable: Vec<Retryable>,
+}
+
+impl Default for RetryPolicy {
+    /// 5 attempts, waiting 1s, 2s, 4s then 8s, retrying transport errors, `429`s and `5xx`s
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retryable: vec![
+                Retryable::Transport,
+                Retryable::TooManyRequests,
+                Retryable::ServerError,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Give up on the first failure
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Wait before the second attempt, doubled (by default) after every further failure
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn retryable(mut self, retryable: Vec<Retryable>) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    /// Whether `err`, returned by attempt number `attempt`, is worth another one
+    pub fn should_retry(&self, err: &Report, attempt: u32) -> bool {
+        attempt < self.max_attempts
+            && Retryable::classify(err).is_some_and(|kind| self.retryable.contains(&kind))
+    }
+
+    /// Wait after attempt number `attempt` failed, before jitter
+    pub fn backoff_after(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()))
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff_after(attempt)
+            .mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random())
+    }
+
+    /// Run `operation` until it succeeds, fails permanently or runs out of attempts.
+    ///
+    /// `operation` receives the attempt number, starting at 1.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation(attempt).await {
+                Ok(value) => return Ok(value),
+                Err(err) if self.should_retry(&err, attempt) => {
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Uniform in `[0, 1)`, good enough to spread retries apart
+fn random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    hasher.write_u128(nanos);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_providers::{JsonRpcError, WsClientError};
+
+    #[test]
+    fn backs_off_exponentially_up_to_max() {
+        let policy = RetryPolicy::default().backoff(Duration::from_secs(1), Duration::from_secs(5));
+        let waits: Vec<u64> = (1..=5)
+            .map(|attempt| policy.backoff_after(attempt).as_secs())
+            .collect();
+        assert_eq!(waits, [1, 2, 4, 5, 5]);
+    }
+
+    #[test]
+    fn retries_only_listed_statuses() {
+        let policy = RetryPolicy::default();
+        let status = |status| {
+            Report::new(GraphQlClientError::Status {
+                status,
+                body: String::new(),
+            })
+        };
+        assert!(policy.should_retry(&status(StatusCode::BAD_GATEWAY), 1));
+        assert!(!policy.should_retry(&status(StatusCode::BAD_GATEWAY), 5));
+        assert!(!policy.should_retry(&status(StatusCode::NOT_FOUND), 1));
+        assert!(!policy.should_retry(&eyre::eyre!("Invalid cursor"), 1));
+        let truncated = io::Error::new(ErrorKind::UnexpectedEof, "Received 1 of 2 bytes");
+        assert!(policy.should_retry(&truncated.into(), 1));
+        let timed_out = io::Error::new(ErrorKind::TimedOut, "No answer within 30s");
+        assert!(policy.should_retry(&timed_out.into(), 1));
+    }
+
+    #[test]
+    fn retries_dropped_rpc_connections() {
+        let policy = RetryPolicy::default();
+        let dropped = ProviderError::from(WsClientError::UnexpectedClose);
+        let dropped = crate::contract::rpc_error("Failed to get logs", dropped);
+        assert!(policy.should_retry(&dropped, 1));
+
+        let rejected = ProviderError::from(HttpClientError::JsonRpcError(JsonRpcError {
+            code: -32005,
+            message: "query returned more than 10000 results".to_string(),
+            data: None,
+        }));
+        assert!(!policy.should_retry(&Report::new(rejected), 1));
+    }
+}
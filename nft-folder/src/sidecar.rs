@@ -0,0 +1,88 @@
+use crate::download::part_path;
+use crate::token::NftToken;
+use eyre::Result;
+use serde_json::{json, Value};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Metadata saved beside a token's media, so the folder stands on its own.
+///
+/// The common fields of the metadata standards are lifted to the top level,
+/// the untouched metadata is kept under `metadata`.
+pub fn sidecar(token: &NftToken) -> Value {
+    let field = |key: &str| {
+        token
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(key))
+            .filter(|value| !value.is_null())
+            .cloned()
+    };
+    json!({
+        "name": token.name.clone().map(Value::String).or_else(|| field("name")),
+        "description": field("description"),
+        "attributes": field("attributes").or_else(|| field("traits")),
+        "external_url": field("external_url"),
+        "chain": token.chain,
+        "contract": token.collection_address,
+        "collection_name": token.collection_name,
+        "token_id": token.token_id,
+        "token_standard": token.standard,
+        "token_url": token.token_url,
+        "balance": token.balance,
+        "metadata": token.metadata,
+    })
+}
+
+/// Path of the sidecar of the media saved as `{name}.{extension}` in `dir`,
+/// whatever extension the media gets
+pub fn sidecar_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.json"))
+}
+
+/// Write `sidecar` to `path`, replacing any previous one at once
+pub(crate) fn write_sidecar(path: &Path, sidecar: &Value) -> Result<()> {
+    let part_path = part_path(path);
+    let mut file = File::create(&part_path)?;
+    file.write_all(&serde_json::to_vec_pretty(sidecar)?)?;
+    file.sync_all()?;
+    fs::rename(part_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifts_standard_fields() {
+        let token = NftToken {
+            collection_name: Some("Nouns".to_string()),
+            collection_address: Some("0x9c8ff314c9bc7f6e59a9d9225fb22946427edc03".to_string()),
+            token_id: Some("1".to_string()),
+            metadata: Some(json!({
+                "name": "Noun 1",
+                "description": "Nouns DAO",
+                "attributes": [{"trait_type": "head", "value": "ape"}],
+            })),
+            ..Default::default()
+        };
+
+        let sidecar = sidecar(&token);
+        assert_eq!(sidecar["name"], "Noun 1");
+        assert_eq!(sidecar["attributes"][0]["value"], "ape");
+        assert!(sidecar["external_url"].is_null());
+        assert_eq!(sidecar["metadata"]["description"], "Nouns DAO");
+    }
+
+    #[test]
+    fn sidecar_beside_media() {
+        assert_eq!(
+            sidecar_path(Path::new("base"), "Noun 1.5"),
+            Path::new("base/Noun 1.5.json")
+        );
+    }
+}
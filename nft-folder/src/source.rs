@@ -0,0 +1,110 @@
+pub mod alchemy;
+pub mod onchain;
+pub mod opensea;
+pub mod zora;
+
+pub use alchemy::AlchemySource;
+pub use onchain::OnchainSource;
+pub use opensea::OpenSeaSource;
+pub use zora::ZoraRequest;
+
+use crate::chain::Chain;
+use crate::token::NftToken;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+
+/// One page of tokens returned by an [`NftSource`]
+#[derive(Debug)]
+pub struct Page {
+    pub tokens: Vec<NftToken>,
+    /// Cursor to request the following page with. `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Problems that didn't stop the page from being listed, for the caller to report
+    pub warnings: Vec<String>,
+}
+
+/// A backend able to list the tokens held by an address.
+#[async_trait]
+pub trait NftSource: Send + Sync {
+    /// Fetch the page of tokens owned by `owner` that follows `cursor`.
+    async fn fetch_page(&self, owner: &str, cursor: Option<String>) -> Result<Page>;
+
+    /// Chains the source lists tokens from, `None` when only known from the tokens themselves
+    fn chains(&self) -> Option<Vec<Chain>> {
+        None
+    }
+
+    /// Every token owned by `owner`, paging through the source as the stream is polled.
+    ///
+    /// The stream ends after the first error.
+    fn tokens<'a>(&'a self, owner: &'a str) -> BoxStream<'a, Result<NftToken>> {
+        // `None` once the last page has been requested
+        let cursor: Option<Option<String>> = Some(None);
+        stream::unfold(cursor, move |cursor| async move {
+            let cursor = cursor?;
+            match self.fetch_page(owner, cursor).await {
+                Ok(page) if page.tokens.is_empty() && page.next_cursor.is_none() => None,
+                Ok(page) => {
+                    let next = page.next_cursor.map(Some);
+                    Some((stream::iter(page.tokens.into_iter().map(Ok)).boxed(), next))
+                }
+                Err(err) => Some((stream::once(async { Err(err) }).boxed(), None)),
+            }
+        })
+        .flatten()
+        .boxed()
+    }
+}
+
+/// Position of sources listing one chain after the other.
+///
+/// Serialized as `{chain index}:{cursor within that chain}`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ChainCursor {
+    pub index: usize,
+    pub page: Option<String>,
+}
+
+impl ChainCursor {
+    pub fn parse(cursor: Option<String>) -> Result<Self> {
+        let Some(cursor) = cursor else {
+            return Ok(ChainCursor::default());
+        };
+        let (index, page) = cursor
+            .split_once(':')
+            .ok_or_else(|| eyre!("Invalid cursor {cursor}"))?;
+        Ok(ChainCursor {
+            index: index
+                .parse()
+                .map_err(|err| eyre!("Invalid cursor {cursor}: {err}"))?,
+            page: Some(page.to_string()).filter(|page| !page.is_empty()),
+        })
+    }
+
+    /// Cursor following this one, moving to the next of `chains` once `page` runs out
+    pub fn next(&self, page: Option<String>, chains: usize) -> Option<String> {
+        match page.filter(|page| !page.is_empty()) {
+            Some(page) => Some(format!("{}:{page}", self.index)),
+            None if self.index + 1 < chains => Some(format!("{}:", self.index + 1)),
+            None => None,
+        }
+    }
+}
+
+/// Sources selectable from the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SourceKind {
+    /// Zora's public GraphQL API
+    #[default]
+    Zora,
+    /// ERC-721 and ERC-1155 transfer logs read through the RPC
+    Onchain,
+    /// Alchemy's NFT API
+    Alchemy,
+    /// OpenSea's v2 API
+    Opensea,
+}
@@ -0,0 +1,258 @@
+use super::{ChainCursor, NftSource, Page};
+use crate::chain::Chain;
+use crate::rate_limit::{self, RateLimit};
+use crate::token::{NftImage, NftToken, TokenStandard};
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use reqwest::{Client, Response};
+use serde::Deserialize;
+use serde_json::Value;
+
+const PAGE_SIZE: &str = "100";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OwnedNfts {
+    owned_nfts: Vec<AlchemyNft>,
+    page_key: Option<String>,
+}
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct AlchemyNft {
+    contract: AlchemyContract,
+    token_id: String,
+    token_type: Option<String>,
+    name: Option<String>,
+    token_uri: Option<String>,
+    image: AlchemyImage,
+    /// Older responses list media here instead of `image`
+    media: Vec<AlchemyMedia>,
+    raw: AlchemyRaw,
+    balance: Option<String>,
+}
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct AlchemyContract {
+    address: Option<String>,
+    name: Option<String>,
+}
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct AlchemyImage {
+    cached_url: Option<String>,
+    original_url: Option<String>,
+    content_type: Option<String>,
+    size: Option<Value>,
+}
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct AlchemyMedia {
+    gateway: Option<String>,
+    raw: Option<String>,
+    format: Option<String>,
+}
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct AlchemyRaw {
+    token_uri: Option<String>,
+    metadata: Option<Value>,
+}
+
+impl AlchemyNft {
+    fn into_token(self, chain: Chain) -> NftToken {
+        let metadata = self.raw.metadata.filter(|metadata| !metadata.is_null());
+        let metadata_field = |key: &str| {
+            metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(key))
+                .and_then(Value::as_str)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+
+        let media = self.media.into_iter().next().unwrap_or_default();
+        let image = match (self.image.cached_url, self.image.original_url) {
+            (Some(url), _) | (None, Some(url)) => NftImage::Object {
+                url,
+                size: self.image.size,
+                mime_type: self.image.content_type,
+            },
+            _ => match media.gateway.or(media.raw) {
+                Some(url) => NftImage::Object {
+                    url,
+                    size: None,
+                    mime_type: media.format.map(|format| format!("image/{format}")),
+                },
+                None => NftImage::Null,
+            },
+        };
+
+        let standard = match self.token_type.as_deref() {
+            Some("ERC721") => Some(TokenStandard::Erc721),
+            Some("ERC1155") => Some(TokenStandard::Erc1155),
+            _ => None,
+        };
+        let balance = match standard {
+            Some(TokenStandard::Erc1155) => self.balance.and_then(|balance| balance.parse().ok()),
+            _ => None,
+        };
+
+        NftToken {
+            image,
+            name: self.name.or_else(|| metadata_field("name")),
+            collection_name: self.contract.name,
+            collection_address: self.contract.address,
+            token_url: self.raw.token_uri.or(self.token_uri),
+            token_id: Some(self.token_id),
+            metadata,
+            standard,
+            balance,
+            chain: Some(chain),
+        }
+    }
+}
+
+/// [`NftSource`] for Alchemy's `getNFTsForOwner` NFT API
+#[derive(Debug, Clone)]
+pub struct AlchemySource {
+    client: Client,
+    api_key: String,
+    chains: Vec<Chain>,
+    rate_limit: Option<RateLimit>,
+    base_url: Option<String>,
+}
+
+impl AlchemySource {
+    pub fn new(client: Client, api_key: String) -> Self {
+        AlchemySource {
+            client,
+            api_key,
+            chains: vec![Chain::Ethereum],
+            rate_limit: None,
+            base_url: None,
+        }
+    }
+
+    /// Chains to list tokens from (default Ethereum only)
+    pub fn chains(mut self, chains: Vec<Chain>) -> Self {
+        self.chains = chains;
+        self
+    }
+
+    /// Limit the requests sent to the API (default unlimited, waiting out `429`s)
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Send every request to `url` instead of `https://{network}.g.alchemy.com`,
+    /// e.g. a local server replaying recorded responses
+    pub fn base_url(mut self, url: String) -> Self {
+        self.base_url = Some(url);
+        self
+    }
+
+    fn endpoint(&self, chain: Chain) -> String {
+        let base = self.base_url.clone().unwrap_or_else(|| {
+            let network = match chain {
+                Chain::Ethereum => "eth-mainnet",
+                Chain::Zora => "zora-mainnet",
+                Chain::Base => "base-mainnet",
+                Chain::Optimism => "opt-mainnet",
+            };
+            format!("https://{network}.g.alchemy.com")
+        });
+        format!(
+            "{}/nft/v3/{}/getNFTsForOwner",
+            base.trim_end_matches('/'),
+            self.api_key
+        )
+    }
+}
+
+#[async_trait]
+impl NftSource for AlchemySource {
+    /// Chains are listed one after the other, with the cursor holding
+    /// the index of the current chain and its `pageKey`.
+    async fn fetch_page(&self, owner: &str, cursor: Option<String>) -> Result<Page> {
+        let cursor = ChainCursor::parse(cursor)?;
+        let Some(&chain) = self.chains.get(cursor.index) else {
+            return Ok(Page {
+                tokens: vec![],
+                next_cursor: None,
+                warnings: vec![],
+            });
+        };
+
+        let mut query = vec![
+            ("owner", owner),
+            ("withMetadata", "true"),
+            ("pageSize", PAGE_SIZE),
+        ];
+        if let Some(page_key) = &cursor.page {
+            query.push(("pageKey", page_key));
+        }
+        let request = self.client.get(self.endpoint(chain)).query(&query);
+        // The API key is part of the URL, which reqwest errors would print
+        let response: OwnedNfts = rate_limit::send(request, self.rate_limit)
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(reqwest::Error::without_url)?
+            .json()
+            .await
+            .map_err(|err| eyre!("Failed to parse JSON response: {}", err.without_url()))?;
+
+        Ok(Page {
+            tokens: response
+                .owned_nfts
+                .into_iter()
+                .map(|nft| nft.into_token(chain))
+                .collect(),
+            next_cursor: cursor.next(response.page_key, self.chains.len()),
+            warnings: vec![],
+        })
+    }
+
+    fn chains(&self) -> Option<Vec<Chain>> {
+        Some(self.chains.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_owned_nfts() {
+        let response: OwnedNfts = serde_json::from_str(
+            r#"{
+                "ownedNfts": [{
+                    "contract": {"address": "0x495f947276749ce646f68ac8c248420045cb7b5e", "name": "OpenSea Shared Storefront", "tokenType": "ERC1155"},
+                    "tokenId": "42",
+                    "tokenType": "ERC1155",
+                    "name": null,
+                    "tokenUri": "https://api.opensea.io/api/v1/metadata/42",
+                    "image": {"cachedUrl": null, "originalUrl": null},
+                    "raw": {"tokenUri": "ipfs://QmToken/42", "metadata": {"name": "Edition", "image": "ipfs://QmImage"}},
+                    "balance": "5"
+                }],
+                "pageKey": "next",
+                "totalCount": 1
+            }"#,
+        )
+        .unwrap();
+
+        let token = response
+            .owned_nfts
+            .into_iter()
+            .next()
+            .unwrap()
+            .into_token(Chain::Base);
+        assert_eq!(token.name.as_deref(), Some("Edition"));
+        assert_eq!(token.token_url.as_deref(), Some("ipfs://QmToken/42"));
+        assert_eq!(token.standard, Some(TokenStandard::Erc1155));
+        assert_eq!(token.balance, Some(5));
+        assert_eq!(token.chain, Some(Chain::Base));
+        assert!(matches!(token.image, NftImage::Null));
+    }
+}
@@ -0,0 +1,424 @@
+use super::{NftSource, Page};
+use crate::chain::Chain;
+use crate::contract::{call_error, rpc_error, ContractReader, TokenUriReader};
+use crate::token::{NftImage, NftToken, TokenStandard};
+use async_trait::async_trait;
+use ethers::abi::{decode, ParamType, Token};
+use ethers::types::{Address, Filter, Log, H256, U256};
+use ethers::utils::keccak256;
+use ethers_providers::{Middleware, MiddlewareError};
+use eyre::{eyre, Result};
+use futures::{stream, StreamExt, TryStreamExt};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+pub(crate) const TRANSFER_EVENT: &str = "Transfer(address,address,uint256)";
+pub(crate) const TRANSFER_SINGLE_EVENT: &str =
+    "TransferSingle(address,address,address,uint256,uint256)";
+pub(crate) const TRANSFER_BATCH_EVENT: &str =
+    "TransferBatch(address,address,address,uint256[],uint256[])";
+/// Candidates confirmed with `ownerOf` per page
+const PAGE_SIZE: usize = 50;
+/// Parallel `eth_call`s while confirming a page
+const CONCURRENT_CALLS: usize = 8;
+
+/// (contract, token id, standard)
+pub(crate) type TokenRef = (Address, U256, TokenStandard);
+
+/// Tokens received by an owner, in the order first received
+struct Received {
+    tokens: Arc<Vec<TokenRef>>,
+    /// First block not scanned yet
+    next_block: u64,
+}
+
+/// [`NftSource`] rebuilding ERC-721 and ERC-1155 holdings from chain, without an indexer.
+///
+/// Every `Transfer`, `TransferSingle` and `TransferBatch` log sent to the owner is
+/// collected with chunked `eth_getLogs` calls, then each token is confirmed with
+/// `ownerOf`/`balanceOf` before its `tokenURI`/`uri` is read.
+pub struct OnchainSource<M> {
+    contracts: Arc<ContractReader<M>>,
+    from_block: u64,
+    block_range: u64,
+    /// Tokens received per owner, so later pages of a listing don't rescan the
+    /// chain and new listings only scan the blocks since
+    received: Mutex<HashMap<Address, Received>>,
+}
+
+impl<M: Middleware + 'static> OnchainSource<M> {
+    /// Source reading the chain through `contracts`' provider
+    pub fn new(contracts: Arc<ContractReader<M>>) -> Self {
+        OnchainSource {
+            contracts,
+            from_block: 0,
+            block_range: 100_000,
+            received: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// First block scanned for transfers (default 0)
+    pub fn from_block(mut self, block: u64) -> Self {
+        self.from_block = block;
+        self
+    }
+
+    /// Maximum number of blocks requested per `eth_getLogs` call (default 100 000).
+    ///
+    /// The range is halved whenever the RPC rejects a request and doubled back after
+    /// each successful one, so this is only an upper bound.
+    pub fn block_range(mut self, range: u64) -> Self {
+        self.block_range = range.max(1);
+        self
+    }
+
+    /// Chain the provider is connected to, `None` if unsupported
+    pub(crate) async fn chain(&self) -> Result<Option<Chain>> {
+        self.contracts.chain().await
+    }
+
+    /// Every token ever transferred to `owner`, in the order first received.
+    ///
+    /// The blocks since the last scan are only scanned when `rescan` is set.
+    async fn received(&self, owner: Address, rescan: bool) -> Result<Arc<Vec<TokenRef>>> {
+        let (mut received, mut start) = match self.received.lock().unwrap().get(&owner) {
+            Some(cached) if !rescan => return Ok(Arc::clone(&cached.tokens)),
+            Some(cached) => (cached.tokens.to_vec(), cached.next_block),
+            None => (vec![], self.from_block),
+        };
+
+        let latest = self
+            .contracts
+            .provider()
+            .get_block_number()
+            .await
+            .map_err(|err| rpc_error("Failed to get latest block", err))?
+            .as_u64();
+
+        let mut seen: HashSet<TokenRef> = received.iter().copied().collect();
+        let mut range = self.block_range;
+        while start <= latest {
+            let end = start.saturating_add(range - 1).min(latest);
+            let logs = match self.logs(owner, start, end).await {
+                Ok(logs) => logs,
+                // Providers reject queries over their range or result size limit,
+                // other failures are left to the page's retries
+                Err(err) if range > 1 && err.is_error_response() => {
+                    range /= 2;
+                    continue;
+                }
+                Err(err) => {
+                    let context = format!("Failed to get logs for blocks {start}-{end}");
+                    return Err(rpc_error(context, err));
+                }
+            };
+
+            for token in logs.iter().flat_map(transferred_tokens) {
+                if seen.insert(token) {
+                    received.push(token);
+                }
+            }
+            start = end + 1;
+            // Only the blocks too busy for the full range are queried in smaller ones
+            range = range.saturating_mul(2).min(self.block_range);
+        }
+
+        let received = Arc::new(received);
+        let scanned = Received {
+            tokens: Arc::clone(&received),
+            next_block: start,
+        };
+        self.received.lock().unwrap().insert(owner, scanned);
+        Ok(received)
+    }
+
+    /// Transfer logs to `owner` between `from` and `to` inclusive
+    async fn logs(&self, owner: Address, from: u64, to: u64) -> Result<Vec<Log>, M::Error> {
+        // `to` is the second indexed argument of ERC-721 transfers, but the third for ERC-1155
+        let erc721 = Filter::new()
+            .event(TRANSFER_EVENT)
+            .topic2(owner)
+            .from_block(from)
+            .to_block(to);
+        let erc1155 = Filter::new()
+            .events([TRANSFER_SINGLE_EVENT, TRANSFER_BATCH_EVENT])
+            .topic3(owner)
+            .from_block(from)
+            .to_block(to);
+        let provider = self.contracts.provider();
+        let (mut logs, erc1155) =
+            futures::try_join!(provider.get_logs(&erc721), provider.get_logs(&erc1155))?;
+        logs.extend(erc1155);
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+        Ok(logs)
+    }
+
+    /// Read a token from its contract along with its chain, `None` if `owner` no longer holds it
+    pub(crate) async fn current(
+        &self,
+        owner: Address,
+        token: TokenRef,
+    ) -> Result<Option<NftToken>> {
+        let chain = self.chain().await?;
+        let token = self.token(owner, token).await?;
+        Ok(token.map(|token| NftToken { chain, ..token }))
+    }
+
+    /// Read a token from its contract, `None` if `owner` no longer holds it
+    async fn token(&self, owner: Address, token: TokenRef) -> Result<Option<NftToken>> {
+        match token.2 {
+            TokenStandard::Erc721 => self.erc721(owner, token.0, token.1).await,
+            TokenStandard::Erc1155 => self.erc1155(owner, token.0, token.1).await,
+        }
+    }
+
+    async fn erc721(
+        &self,
+        owner: Address,
+        contract: Address,
+        id: U256,
+    ) -> Result<Option<NftToken>> {
+        let erc721 = self.contracts.erc721(contract);
+        match erc721.owner_of(id).call().await {
+            Ok(current) if current == owner => {}
+            Ok(_) => return Ok(None),
+            // Burned tokens revert
+            Err(err) if err.is_revert() => return Ok(None),
+            Err(err) => {
+                return Err(call_error(
+                    format!("Failed to call ownerOf({id}) on {contract:?}"),
+                    err,
+                ))
+            }
+        }
+
+        let standard = Some(TokenStandard::Erc721);
+        let token_url = self.contracts.token_uri(contract, id, standard).await.ok();
+        let collection_name = erc721.name().call().await.ok();
+        Ok(Some(NftToken {
+            image: NftImage::Null,
+            name: None,
+            collection_name,
+            collection_address: Some(format!("{contract:?}")),
+            token_url,
+            token_id: Some(id.to_string()),
+            metadata: None,
+            standard: Some(TokenStandard::Erc721),
+            balance: None,
+            chain: None,
+        }))
+    }
+
+    async fn erc1155(
+        &self,
+        owner: Address,
+        contract: Address,
+        id: U256,
+    ) -> Result<Option<NftToken>> {
+        let erc1155 = self.contracts.erc1155(contract);
+        let balance = erc1155.balance_of(owner, id).call().await.map_err(|err| {
+            call_error(
+                format!("Failed to call balanceOf({id}) on {contract:?}"),
+                err,
+            )
+        })?;
+        if balance.is_zero() {
+            return Ok(None);
+        }
+
+        let standard = Some(TokenStandard::Erc1155);
+        let token_url = self.contracts.token_uri(contract, id, standard).await.ok();
+        // Optional for ERC-1155, but most collections implement it
+        let collection_name = erc1155.name().call().await.ok();
+        Ok(Some(NftToken {
+            image: NftImage::Null,
+            name: None,
+            collection_name,
+            collection_address: Some(format!("{contract:?}")),
+            token_url,
+            token_id: Some(id.to_string()),
+            metadata: None,
+            standard: Some(TokenStandard::Erc1155),
+            balance: Some(balance.try_into().unwrap_or(u64::MAX)),
+            chain: None,
+        }))
+    }
+}
+
+/// Tokens moved by a `Transfer`, `TransferSingle` or `TransferBatch` log
+pub(crate) fn transferred_tokens(log: &Log) -> Vec<TokenRef> {
+    let contract = log.address;
+    let Some(&signature) = log.topics.first() else {
+        return vec![];
+    };
+    if signature == H256::from(keccak256(TRANSFER_EVENT)) {
+        // ERC-20 shares the signature but doesn't index the third argument
+        if log.topics.len() != 4 {
+            return vec![];
+        }
+        let id = U256::from_big_endian(log.topics[3].as_bytes());
+        vec![(contract, id, TokenStandard::Erc721)]
+    } else if signature == H256::from(keccak256(TRANSFER_SINGLE_EVENT)) {
+        match log.data.get(..32) {
+            Some(id) => vec![(contract, U256::from_big_endian(id), TokenStandard::Erc1155)],
+            None => vec![],
+        }
+    } else if signature == H256::from(keccak256(TRANSFER_BATCH_EVENT)) {
+        let ids = ParamType::Array(Box::new(ParamType::Uint(256)));
+        let Ok(Some(Token::Array(ids))) =
+            decode(&[ids.clone(), ids], &log.data).map(|decoded| decoded.into_iter().next())
+        else {
+            return vec![];
+        };
+        ids.into_iter()
+            .filter_map(Token::into_uint)
+            .map(|id| (contract, id, TokenStandard::Erc1155))
+            .collect()
+    } else {
+        vec![]
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> NftSource for OnchainSource<M> {
+    async fn fetch_page(&self, owner: &str, cursor: Option<String>) -> Result<Page> {
+        let owner: Address = owner
+            .parse()
+            .map_err(|err| eyre!("Invalid address {owner}: {err}"))?;
+        // Each listing picks up the transfers made since the previous one
+        let rescan = cursor.is_none();
+        let offset = match cursor {
+            Some(cursor) => cursor
+                .parse::<usize>()
+                .map_err(|err| eyre!("Invalid cursor {cursor}: {err}"))?,
+            None => 0,
+        };
+
+        let received = self.received(owner, rescan).await?;
+        let batch: Vec<TokenRef> = received
+            .iter()
+            .skip(offset)
+            .take(PAGE_SIZE)
+            .copied()
+            .collect();
+        let tokens: Vec<Option<NftToken>> = stream::iter(batch)
+            .map(|token| self.token(owner, token))
+            .buffered(CONCURRENT_CALLS)
+            .try_collect()
+            .await?;
+
+        let chain = self.chain().await?;
+        let next = offset + PAGE_SIZE;
+        Ok(Page {
+            tokens: tokens
+                .into_iter()
+                .flatten()
+                .map(|token| NftToken { chain, ..token })
+                .collect(),
+            next_cursor: (next < received.len()).then(|| next.to_string()),
+            warnings: vec![],
+        })
+    }
+
+    /// The provider's chain, known once a page has been fetched
+    fn chains(&self) -> Option<Vec<Chain>> {
+        // Tokens on unsupported chains are keyed as Ethereum ones
+        let chain = self.contracts.known_chain()?;
+        Some(vec![chain.unwrap_or(Chain::Ethereum)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Manifest, ManifestEntry};
+    use crate::sync::{Removal, ARCHIVE_DIR};
+    use crate::Downloader;
+    use ethers::abi::encode;
+    use ethers::types::U64;
+    use ethers_providers::Provider;
+    use std::fs;
+
+    fn log(event: &str, topics: &[H256], data: Vec<u8>) -> Log {
+        Log {
+            address: Address::repeat_byte(0xaa),
+            topics: [&[H256::from(keccak256(event))][..], topics].concat(),
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decodes_transferred_tokens() {
+        let (from, to) = (H256::repeat_byte(1), H256::repeat_byte(2));
+        let contract = Address::repeat_byte(0xaa);
+        let id = |id: u64| H256::from_low_u64_be(id);
+
+        let erc721 = log(TRANSFER_EVENT, &[from, to, id(7)], vec![]);
+        assert_eq!(
+            transferred_tokens(&erc721),
+            [(contract, U256::from(7), TokenStandard::Erc721)]
+        );
+        // ERC-20 amounts are in the data
+        let erc20 = log(TRANSFER_EVENT, &[from, to], id(7).as_bytes().to_vec());
+        assert!(transferred_tokens(&erc20).is_empty());
+
+        let single_data = encode(&[Token::Uint(U256::from(8)), Token::Uint(U256::one())]);
+        let single = log(TRANSFER_SINGLE_EVENT, &[from, from, to], single_data);
+        assert_eq!(
+            transferred_tokens(&single),
+            [(contract, U256::from(8), TokenStandard::Erc1155)]
+        );
+
+        let uints = |values: &[u64]| {
+            Token::Array(
+                values
+                    .iter()
+                    .map(|value| Token::Uint(U256::from(*value)))
+                    .collect(),
+            )
+        };
+        let batch_data = encode(&[uints(&[9, 10]), uints(&[1, 5])]);
+        let batch = log(TRANSFER_BATCH_EVENT, &[from, from, to], batch_data);
+        let ids: Vec<U256> = transferred_tokens(&batch)
+            .into_iter()
+            .map(|token| token.1)
+            .collect();
+        assert_eq!(ids, [U256::from(9), U256::from(10)]);
+    }
+
+    #[tokio::test]
+    async fn sync_archives_everything_once_sold() {
+        let (provider, mock) = Provider::mocked();
+        // Responses are popped from the back
+        mock.push::<U256, _>(U256::one()).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<U64, _>(U64::zero()).unwrap();
+        let source = OnchainSource::new(Arc::new(ContractReader::new(Arc::new(provider))));
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut manifest = Manifest::default();
+        fs::write(dir.join("sold.png"), "1").unwrap();
+        let sold = NftToken {
+            collection_address: Some("0xabc".to_string()),
+            token_id: Some("1".to_string()),
+            chain: Some(Chain::Ethereum),
+            ..Default::default()
+        };
+        manifest.insert("sold.png".to_string(), ManifestEntry::new(&sold));
+        manifest.save(dir).unwrap();
+
+        Downloader::new(reqwest::Client::new())
+            .source(Arc::new(source))
+            .sync(Removal::Archive)
+            .run(&format!("{:?}", Address::repeat_byte(1)), dir.to_path_buf())
+            .await
+            .unwrap();
+        assert!(!dir.join("sold.png").exists());
+        assert!(dir.join(ARCHIVE_DIR).join("sold.png").is_file());
+    }
+}
@@ -0,0 +1,279 @@
+use super::{ChainCursor, NftSource, Page};
+use crate::chain::Chain;
+use crate::rate_limit::{self, RateLimit};
+use crate::token::{NftImage, NftToken, TokenStandard};
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use reqwest::Client;
+use serde::Deserialize;
+
+const PAGE_SIZE: &str = "200";
+
+#[derive(Deserialize, Debug)]
+struct AccountNfts {
+    nfts: Vec<OpenSeaNft>,
+    next: Option<String>,
+}
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct OpenSeaNft {
+    identifier: String,
+    collection: Option<String>,
+    contract: Option<String>,
+    token_standard: Option<String>,
+    name: Option<String>,
+    image_url: Option<String>,
+    display_image_url: Option<String>,
+    metadata_url: Option<String>,
+    is_disabled: bool,
+    is_nsfw: bool,
+    is_suspicious: bool,
+}
+
+impl OpenSeaNft {
+    /// Flagged as spam or hidden by OpenSea
+    fn is_hidden(&self) -> bool {
+        self.is_disabled || self.is_nsfw || self.is_suspicious
+    }
+
+    fn into_token(self, chain: Chain) -> NftToken {
+        // Missing URLs are often sent as empty strings
+        let present = |url: Option<String>| url.filter(|url| !url.is_empty());
+        let image = match present(self.display_image_url).or(present(self.image_url)) {
+            Some(url) => NftImage::Url(url),
+            None => NftImage::Null,
+        };
+        let standard = match self.token_standard.as_deref() {
+            Some("erc721") => Some(TokenStandard::Erc721),
+            Some("erc1155") => Some(TokenStandard::Erc1155),
+            _ => None,
+        };
+
+        NftToken {
+            image,
+            name: self.name,
+            collection_name: self.collection,
+            collection_address: self.contract,
+            token_url: present(self.metadata_url),
+            token_id: Some(self.identifier),
+            metadata: None,
+            standard,
+            balance: None,
+            chain: Some(chain),
+        }
+    }
+}
+
+/// [`NftSource`] for OpenSea's v2 account NFTs API
+#[derive(Debug, Clone)]
+pub struct OpenSeaSource {
+    client: Client,
+    api_key: String,
+    chains: Vec<Chain>,
+    rate_limit: Option<RateLimit>,
+    include_hidden: bool,
+    base_url: String,
+}
+
+impl OpenSeaSource {
+    pub fn new(client: Client, api_key: String) -> Self {
+        OpenSeaSource {
+            client,
+            api_key,
+            chains: vec![Chain::Ethereum],
+            rate_limit: None,
+            include_hidden: false,
+            base_url: "https://api.opensea.io".to_string(),
+        }
+    }
+
+    /// Chains to list tokens from (default Ethereum only)
+    pub fn chains(mut self, chains: Vec<Chain>) -> Self {
+        self.chains = chains;
+        self
+    }
+
+    /// Limit the requests sent to the API (default unlimited, waiting out `429`s)
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Keep tokens OpenSea hides as disabled, NSFW or suspicious (default false)
+    pub fn include_hidden(mut self, include: bool) -> Self {
+        self.include_hidden = include;
+        self
+    }
+
+    /// Send requests to `url` instead of `https://api.opensea.io`
+    pub fn base_url(mut self, url: String) -> Self {
+        self.base_url = url;
+        self
+    }
+
+    fn endpoint(&self, chain: Chain, owner: &str) -> String {
+        let chain = match chain {
+            Chain::Ethereum => "ethereum",
+            Chain::Zora => "zora",
+            Chain::Base => "base",
+            Chain::Optimism => "optimism",
+        };
+        format!(
+            "{}/api/v2/chain/{chain}/account/{owner}/nfts",
+            self.base_url.trim_end_matches('/')
+        )
+    }
+
+    fn page(&self, response: AccountNfts, chain: Chain, cursor: &ChainCursor) -> Page {
+        Page {
+            tokens: response
+                .nfts
+                .into_iter()
+                .filter(|nft| self.include_hidden || !nft.is_hidden())
+                .map(|nft| nft.into_token(chain))
+                .collect(),
+            next_cursor: cursor.next(response.next, self.chains.len()),
+            warnings: vec![],
+        }
+    }
+}
+
+#[async_trait]
+impl NftSource for OpenSeaSource {
+    /// Chains are listed one after the other, with the cursor holding
+    /// the index of the current chain and its `next` cursor.
+    async fn fetch_page(&self, owner: &str, cursor: Option<String>) -> Result<Page> {
+        let cursor = ChainCursor::parse(cursor)?;
+        let Some(&chain) = self.chains.get(cursor.index) else {
+            return Ok(Page {
+                tokens: vec![],
+                next_cursor: None,
+                warnings: vec![],
+            });
+        };
+
+        let mut query = vec![("limit", PAGE_SIZE)];
+        if let Some(next) = &cursor.page {
+            query.push(("next", next));
+        }
+        let request = self
+            .client
+            .get(self.endpoint(chain, owner))
+            .header("X-API-KEY", &self.api_key)
+            .query(&query);
+        let response: AccountNfts = rate_limit::send(request, self.rate_limit)
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .map_err(|err| eyre!("Failed to parse JSON response: {}", err))?;
+
+        Ok(self.page(response, chain, &cursor))
+    }
+
+    fn chains(&self) -> Option<Vec<Chain>> {
+        Some(self.chains.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = r#"{
+        "nfts": [
+            {
+                "identifier": "7",
+                "collection": "nouns",
+                "contract": "0x9c8ff314c9bc7f6e59a9d9225fb22946427edc03",
+                "token_standard": "erc721",
+                "name": "Noun 7",
+                "image_url": "https://i.seadn.io/noun-7.png",
+                "display_image_url": "",
+                "display_animation_url": null,
+                "metadata_url": "",
+                "opensea_url": "https://opensea.io/assets/ethereum/0x9c8ff314c9bc7f6e59a9d9225fb22946427edc03/7",
+                "updated_at": "2024-03-01T12:00:00.000000",
+                "is_disabled": false,
+                "is_nsfw": false
+            },
+            {
+                "identifier": "1",
+                "collection": "free-mint",
+                "contract": "0x0000000000000000000000000000000000000001",
+                "token_standard": "erc1155",
+                "name": "Claim your reward",
+                "image_url": "https://i.seadn.io/spam.png",
+                "display_image_url": "https://i.seadn.io/spam.png",
+                "metadata_url": "https://example.com/1.json",
+                "is_disabled": true,
+                "is_nsfw": false,
+                "is_suspicious": true
+            },
+            {
+                "identifier": "3",
+                "collection": "art",
+                "contract": "0x0000000000000000000000000000000000000002",
+                "token_standard": "erc1155",
+                "name": null,
+                "image_url": null,
+                "display_image_url": null,
+                "metadata_url": "ipfs://QmToken/3",
+                "is_disabled": false,
+                "is_nsfw": true
+            }
+        ],
+        "next": "LXBrPTEyMw=="
+    }"#;
+
+    fn source(include_hidden: bool) -> OpenSeaSource {
+        OpenSeaSource::new(Client::new(), String::new())
+            .chains(vec![Chain::Ethereum, Chain::Base])
+            .include_hidden(include_hidden)
+    }
+
+    #[test]
+    fn maps_account_nfts() {
+        let response: AccountNfts = serde_json::from_str(RESPONSE).unwrap();
+        let page = source(false).page(response, Chain::Ethereum, &ChainCursor::default());
+        assert_eq!(page.next_cursor.as_deref(), Some("0:LXBrPTEyMw=="));
+        assert_eq!(page.tokens.len(), 1);
+
+        let token = &page.tokens[0];
+        assert!(
+            matches!(&token.image, NftImage::Url(url) if url == "https://i.seadn.io/noun-7.png")
+        );
+        assert_eq!(token.name.as_deref(), Some("Noun 7"));
+        assert_eq!(token.collection_name.as_deref(), Some("nouns"));
+        assert_eq!(
+            token.collection_address.as_deref(),
+            Some("0x9c8ff314c9bc7f6e59a9d9225fb22946427edc03")
+        );
+        assert_eq!(token.token_id.as_deref(), Some("7"));
+        assert_eq!(token.token_url, None);
+        assert_eq!(token.standard, Some(TokenStandard::Erc721));
+        assert_eq!(token.chain, Some(Chain::Ethereum));
+    }
+
+    #[test]
+    fn keeps_hidden_nfts_when_asked() {
+        let response: AccountNfts = serde_json::from_str(RESPONSE).unwrap();
+        let cursor = ChainCursor {
+            index: 1,
+            page: None,
+        };
+        let page = source(true).page(response, Chain::Base, &cursor);
+        assert_eq!(page.next_cursor.as_deref(), Some("1:LXBrPTEyMw=="));
+        let ids: Vec<_> = page
+            .tokens
+            .iter()
+            .filter_map(|token| token.token_id.as_deref())
+            .collect();
+        assert_eq!(ids, ["7", "1", "3"]);
+        assert!(matches!(page.tokens[2].image, NftImage::Null));
+        assert_eq!(
+            page.tokens[2].token_url.as_deref(),
+            Some("ipfs://QmToken/3")
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use super::{NftSource, Page};
+use crate::chain::Chain;
+use crate::graphql::{GraphQlClient, GraphQlClientError, GraphQlResponse};
+use crate::rate_limit::RateLimit;
+use crate::token::NftToken;
+use async_trait::async_trait;
+use eyre::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NftNode {
+    pub token: ZoraToken,
+}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ZoraToken {
+    #[serde(flatten)]
+    pub token: NftToken,
+    pub network_info: Option<NetworkInfo>,
+}
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NetworkInfo {
+    pub network: String,
+    pub chain: String,
+}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PageInfo {
+    pub end_cursor: Option<String>,
+    pub has_next_page: bool,
+    limit: i32,
+}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NftNodes {
+    /// Nodes that failed to resolve are nulled out of partial responses
+    pub nodes: Vec<Option<NftNode>>,
+    pub page_info: PageInfo,
+}
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NftData {
+    pub tokens: Option<NftNodes>,
+}
+
+const TOKENS_QUERY: &str = r#"
+query NFTsForAddress(
+    $networks: [NetworkInput!],
+    $pagination: PaginationInput,
+    $where: TokensQueryInput
+) {
+    tokens(networks: $networks, pagination: $pagination, where: $where) {
+        nodes {
+            token {
+                tokenId
+                tokenUrl
+                collectionName
+                collectionAddress
+                tokenStandard
+                networkInfo {
+                    network
+                    chain
+                }
+                name
+                metadata
+                image {
+                    url
+                    size
+                    mimeType
+                }
+            }
+        }
+        pageInfo {
+            endCursor
+            hasNextPage
+            limit
+        }
+    }
+}
+"#;
+
+/// [`NftSource`] backed by Zora's public GraphQL API
+#[derive(Debug, Clone)]
+pub struct ZoraRequest {
+    client: GraphQlClient,
+    chains: Vec<Chain>,
+}
+
+impl ZoraRequest {
+    const API: &'static str = "https://api.zora.co/graphql";
+    /// Limit of the public API
+    pub const RATE_LIMIT: RateLimit = RateLimit::per_minute(30);
+
+    pub fn new(client: Client) -> Self {
+        ZoraRequest {
+            client: GraphQlClient::new(client, ZoraRequest::API).rate_limit(Self::RATE_LIMIT),
+            chains: vec![Chain::Ethereum],
+        }
+    }
+
+    /// Requests allowed to the API (default [`ZoraRequest::RATE_LIMIT`])
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.client = self.client.rate_limit(limit);
+        self
+    }
+
+    /// Chains to list tokens from (default Ethereum only)
+    pub fn chains(mut self, chains: Vec<Chain>) -> Self {
+        self.chains = chains;
+        self
+    }
+
+    /// `network` and `chain` of Zora's `NetworkInput`
+    fn network(chain: Chain) -> (&'static str, &'static str) {
+        match chain {
+            Chain::Ethereum => ("ETHEREUM", "MAINNET"),
+            Chain::Zora => ("ZORA", "ZORA_MAINNET"),
+            Chain::Base => ("BASE", "BASE_MAINNET"),
+            Chain::Optimism => ("OPTIMISM", "OPTIMISM_MAINNET"),
+        }
+    }
+
+    fn chain(info: &NetworkInfo) -> Option<Chain> {
+        Chain::ALL
+            .into_iter()
+            .find(|&chain| ZoraRequest::network(chain).1 == info.chain)
+    }
+
+    /// Fetch a single page of tokens owned by `address`, starting after `cursor`.
+    pub async fn fetch_nodes(
+        &self,
+        cursor: Option<String>,
+        address: &str,
+    ) -> Result<GraphQlResponse<NftData>, GraphQlClientError> {
+        let networks: Vec<Value> = self
+            .chains
+            .iter()
+            .map(|&chain| {
+                let (network, chain) = ZoraRequest::network(chain);
+                json!({ "network": network, "chain": chain })
+            })
+            .collect();
+        let variables = json!({
+            "networks": networks,
+            "pagination": { "limit": 200, "after": cursor },
+            "where": { "ownerAddresses": [address] },
+        });
+
+        self.client
+            .query(TOKENS_QUERY, Some("NFTsForAddress"), variables)
+            .await
+    }
+}
+
+#[async_trait]
+impl NftSource for ZoraRequest {
+    async fn fetch_page(&self, owner: &str, cursor: Option<String>) -> Result<Page> {
+        let response = self.fetch_nodes(cursor, owner).await?;
+        let Some(nodes) = response.data.tokens else {
+            return Err(GraphQlClientError::Query(response.errors).into());
+        };
+        let skipped = nodes.nodes.iter().filter(|node| node.is_none()).count();
+        let mut warnings = vec![];
+        if skipped > 0 {
+            let errors: Vec<String> = response.errors.iter().map(ToString::to_string).collect();
+            warnings.push(format!(
+                "Skipped {skipped} tokens Zora failed to resolve: {}",
+                errors.join("; ")
+            ));
+        }
+        let next_cursor = match nodes.page_info.has_next_page {
+            true => nodes.page_info.end_cursor,
+            false => None,
+        };
+        Ok(Page {
+            tokens: nodes
+                .nodes
+                .into_iter()
+                .flatten()
+                .map(|node| {
+                    let mut token = node.token.token;
+                    token.chain = node
+                        .token
+                        .network_info
+                        .as_ref()
+                        .and_then(ZoraRequest::chain);
+                    token
+                })
+                .collect(),
+            next_cursor,
+            warnings,
+        })
+    }
+
+    fn chains(&self) -> Option<Vec<Chain>> {
+        Some(self.chains.clone())
+    }
+}
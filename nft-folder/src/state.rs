@@ -0,0 +1,243 @@
+use crate::token::NftToken;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// File the run state is saved to, beside the manifest in the account's folder
+pub const STATE_FILE: &str = ".nft-folder/state.json";
+/// Minimum time between two saves while downloads settle
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Progress of a run as saved on disk
+#[derive(Serialize, Deserialize, Debug)]
+struct RunState<T> {
+    address: String,
+    /// Cursor of the next page to request, `None` for the first one
+    cursor: Option<String>,
+    /// Every page has been requested
+    listed: bool,
+    /// Tokens listed but not saved yet
+    pending: Vec<T>,
+    /// Tokens that could not be saved
+    failed: Vec<T>,
+}
+
+/// Tracks which tokens of a run are still to be saved, so an interrupted run
+/// can pick up where it stopped instead of paging through the source again.
+pub(crate) struct Checkpoint {
+    /// `None` for runs that leave the saved state alone
+    path: Option<PathBuf>,
+    address: String,
+    cursor: Option<String>,
+    listed: bool,
+    pending: BTreeMap<usize, NftToken>,
+    failed: Vec<NftToken>,
+    next_id: usize,
+    saved: Instant,
+}
+
+impl Checkpoint {
+    /// Start a run for `address` in `dir`, resuming the saved one if `resume` is set.
+    ///
+    /// Returns the tokens the previous run left unsaved, to be queued again first.
+    /// A listing interrupted midway continues from its cursor, a completed one is
+    /// started over from the first page to find the tokens acquired since.
+    pub fn open(dir: &Path, address: &str, resume: bool) -> Result<(Self, Vec<NftToken>)> {
+        let path = dir.join(STATE_FILE);
+        let state = match resume {
+            true => load(&path)?.filter(|state| state.address.eq_ignore_ascii_case(address)),
+            false => None,
+        };
+        let (cursor, unsaved) = match state {
+            Some(mut state) => {
+                state.pending.append(&mut state.failed);
+                let cursor = state.cursor.filter(|_| !state.listed);
+                (cursor, state.pending)
+            }
+            None => (None, vec![]),
+        };
+
+        let checkpoint = Checkpoint {
+            path: Some(path),
+            address: address.to_string(),
+            cursor,
+            listed: false,
+            pending: BTreeMap::new(),
+            failed: vec![],
+            next_id: 0,
+            saved: Instant::now(),
+        };
+        Ok((checkpoint, unsaved))
+    }
+
+    /// Track a run of `address` in memory only, leaving the folder's saved state as is
+    pub fn detached(address: &str) -> Self {
+        Checkpoint {
+            path: None,
+            address: address.to_string(),
+            cursor: None,
+            listed: false,
+            pending: BTreeMap::new(),
+            failed: vec![],
+            next_id: 0,
+            saved: Instant::now(),
+        }
+    }
+
+    /// Cursor of the next page, `None` once every page was requested
+    pub fn cursor(&self) -> Option<Option<String>> {
+        match self.listed {
+            true => None,
+            false => Some(self.cursor.clone()),
+        }
+    }
+
+    /// Record a token about to be saved, returning its id for [`Checkpoint::settle`]
+    pub fn queue(&mut self, token: &NftToken) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(id, token.clone());
+        id
+    }
+
+    /// Record that the tokens of a page were queued, `next` being the following page's cursor
+    pub fn page_done(&mut self, next: Option<String>) -> Result<()> {
+        self.listed = next.is_none();
+        self.cursor = next;
+        self.save()
+    }
+
+    /// Record that a token was saved, or failed to be.
+    ///
+    /// The state is saved at most every [`SAVE_INTERVAL`]. A failed save is
+    /// tried again by the next one, which [`Checkpoint::finish`] always makes.
+    pub fn settle(&mut self, id: usize, saved: bool) {
+        if let Some(token) = self.pending.remove(&id) {
+            if !saved {
+                self.failed.push(token);
+            }
+        }
+        if self.saved.elapsed() >= SAVE_INTERVAL {
+            self.save().ok();
+        }
+    }
+
+    /// Save the state one last time, or remove it once every token was saved
+    pub fn finish(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.listed && self.pending.is_empty() && self.failed.is_empty() {
+            return match fs::remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+        self.save()
+    }
+
+    /// Write the state next to the tokens, replacing the previous one at once
+    fn save(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let state = RunState {
+            address: self.address.clone(),
+            cursor: self.cursor.clone(),
+            listed: self.listed,
+            pending: self.pending.values().collect(),
+            failed: self.failed.iter().collect(),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&state)?)?;
+        fs::rename(&tmp, path)?;
+        self.saved = Instant::now();
+        Ok(())
+    }
+}
+
+fn load(path: &Path) -> Result<Option<RunState<NftToken>>> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    serde_json::from_slice(&json)
+        .map(Some)
+        .map_err(|err| eyre!("Invalid run state in {}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::NftImage;
+
+    fn token(id: &str) -> NftToken {
+        NftToken {
+            image: NftImage::Url(format!("https://example.com/{id}.png")),
+            name: Some(id.to_string()),
+            token_id: Some(id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resumes_unsaved_tokens() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (mut checkpoint, unsaved) = Checkpoint::open(dir, "0xabc", true).unwrap();
+        assert!(unsaved.is_empty());
+        let saved = checkpoint.queue(&token("1"));
+        let failed = checkpoint.queue(&token("2"));
+        checkpoint.queue(&token("3"));
+        checkpoint.page_done(Some("next".to_string())).unwrap();
+        checkpoint.settle(saved, true);
+        checkpoint.settle(failed, false);
+        checkpoint.finish().unwrap();
+        assert!(dir.join(".nft-folder/state.json").is_file());
+
+        let (checkpoint, unsaved) = Checkpoint::open(dir, "0xABC", true).unwrap();
+        assert_eq!(checkpoint.cursor(), Some(Some("next".to_string())));
+        let ids: Vec<_> = unsaved
+            .iter()
+            .filter_map(|t| t.token_id.as_deref())
+            .collect();
+        assert_eq!(ids, ["3", "2"]);
+
+        let (checkpoint, unsaved) = Checkpoint::open(dir, "0xabc", false).unwrap();
+        assert_eq!(checkpoint.cursor(), Some(None));
+        assert!(unsaved.is_empty());
+    }
+
+    #[test]
+    fn lists_again_after_completed_run() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (mut checkpoint, _) = Checkpoint::open(dir, "0xabc", true).unwrap();
+        let saved = checkpoint.queue(&token("1"));
+        let failed = checkpoint.queue(&token("2"));
+        checkpoint.page_done(None).unwrap();
+        assert_eq!(checkpoint.cursor(), None);
+        checkpoint.settle(saved, true);
+        checkpoint.settle(failed, false);
+        checkpoint.finish().unwrap();
+
+        // The failures are queued again beside a new listing
+        let (checkpoint, unsaved) = Checkpoint::open(dir, "0xabc", true).unwrap();
+        assert_eq!(checkpoint.cursor(), Some(None));
+        let ids: Vec<_> = unsaved
+            .iter()
+            .filter_map(|t| t.token_id.as_deref())
+            .collect();
+        assert_eq!(ids, ["2"]);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::chain::Chain;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::token::NftToken;
+use eyre::Result;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+/// Folder the files of tokens no longer owned are moved to
+pub const ARCHIVE_DIR: &str = "_archive";
+
+/// What a sync does with the files of tokens the account no longer owns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Removal {
+    /// Move them into [`ARCHIVE_DIR`], keeping their manifest entries
+    #[default]
+    Archive,
+    /// Delete them
+    Prune,
+}
+
+impl Removal {
+    /// [`Removal::Prune`] if `prune` is set, [`Removal::Archive`] otherwise
+    pub fn from_prune(prune: bool) -> Self {
+        match prune {
+            true => Removal::Prune,
+            false => Removal::Archive,
+        }
+    }
+
+    /// Past tense of the action, for messages
+    pub(crate) fn verb(&self) -> &'static str {
+        match self {
+            Removal::Archive => "Archived",
+            Removal::Prune => "Deleted",
+        }
+    }
+}
+
+/// Chain, lowercase contract address and id of a token.
+///
+/// Tokens without a chain are saved with the Ethereum ones, and identified as such.
+pub(crate) type TokenKey = (Chain, String, String);
+
+pub(crate) fn token_key(token: &NftToken) -> Option<TokenKey> {
+    key(token.chain, &token.collection_address, &token.token_id)
+}
+
+fn entry_key(entry: &ManifestEntry) -> Option<TokenKey> {
+    key(entry.chain, &entry.contract, &entry.token_id)
+}
+
+fn key(chain: Option<Chain>, contract: &Option<String>, id: &Option<String>) -> Option<TokenKey> {
+    Some((
+        chain.unwrap_or(Chain::Ethereum),
+        contract.as_ref()?.to_lowercase(),
+        id.clone()?,
+    ))
+}
+
+/// Tokens with a file in the folder, leaving out archived ones
+pub(crate) fn saved_tokens(manifest: &Manifest) -> HashSet<TokenKey> {
+    let archive_prefix = format!("{ARCHIVE_DIR}/");
+    manifest
+        .files
+        .iter()
+        .filter(|(file, _)| !file.starts_with(&archive_prefix))
+        .filter_map(|(_, entry)| entry_key(entry))
+        .collect()
+}
+
+/// Archive or delete the files of `manifest` whose token is not in `owned`.
+///
+/// Only files recorded in the manifest for one of the listed `chains` are
+/// considered, and entries that don't identify their token are left alone.
+/// Returns the number of files removed.
+pub(crate) fn reconcile(
+    manifest: &mut Manifest,
+    dir: &Path,
+    owned: &HashSet<TokenKey>,
+    chains: &HashSet<Chain>,
+    removal: Removal,
+) -> Result<usize> {
+    remove_where(manifest, dir, removal, |key| {
+        chains.contains(&key.0) && !owned.contains(key)
+    })
+}
+
+/// Archive or delete the files of `tokens`, returning the number of files removed
+pub(crate) fn remove_tokens(
+    manifest: &mut Manifest,
+    dir: &Path,
+    tokens: &HashSet<TokenKey>,
+    removal: Removal,
+) -> Result<usize> {
+    remove_where(manifest, dir, removal, |key| tokens.contains(key))
+}
+
+/// Archive or delete the files of the tokens matching `stale`
+fn remove_where(
+    manifest: &mut Manifest,
+    dir: &Path,
+    removal: Removal,
+    stale: impl Fn(&TokenKey) -> bool,
+) -> Result<usize> {
+    let archive_prefix = format!("{ARCHIVE_DIR}/");
+    let stale: Vec<String> = manifest
+        .files
+        .iter()
+        .filter(|(file, entry)| {
+            !file.starts_with(&archive_prefix) && entry_key(entry).is_some_and(|key| stale(&key))
+        })
+        .map(|(file, _)| file.clone())
+        .collect();
+
+    for file in &stale {
+        let Some(mut entry) = manifest.files.remove(file) else {
+            continue;
+        };
+        let sidecar = entry.sidecar.clone();
+        if removal == Removal::Archive {
+            entry.sidecar = sidecar.as_ref().map(|key| format!("{archive_prefix}{key}"));
+            manifest.insert(format!("{archive_prefix}{file}"), entry);
+        }
+        match remove_file(dir, file, removal) {
+            // Deleted by hand since it was saved
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                manifest.files.remove(&format!("{archive_prefix}{file}"));
+            }
+            Err(err) => return Err(err.into()),
+            Ok(()) => {}
+        }
+        if let Some(sidecar) = sidecar {
+            match remove_file(dir, &sidecar, removal) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(stale.len())
+}
+
+/// Move `file` of `dir` into the archive, or delete it
+fn remove_file(dir: &Path, file: &str, removal: Removal) -> io::Result<()> {
+    let path = dir.join(file);
+    match removal {
+        Removal::Prune => fs::remove_file(path),
+        Removal::Archive => {
+            let archived = dir.join(ARCHIVE_DIR).join(file);
+            if let Some(parent) = archived.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(path, archived)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(contract: &str, id: &str) -> ManifestEntry {
+        ManifestEntry::new(&NftToken {
+            collection_address: Some(contract.to_string()),
+            token_id: Some(id.to_string()),
+            chain: Some(Chain::Ethereum),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn archives_tokens_no_longer_owned() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut manifest = Manifest::default();
+        for (file, id) in [("kept.png", "1"), ("sold.png", "2")] {
+            fs::write(dir.join(file), id).unwrap();
+            manifest.insert(file.to_string(), entry("0xABC", id));
+        }
+
+        let owned = HashSet::from([(Chain::Ethereum, "0xabc".to_string(), "1".to_string())]);
+        let base = HashSet::from([Chain::Base]);
+        assert_eq!(
+            reconcile(&mut manifest, dir, &owned, &base, Removal::Archive).unwrap(),
+            0
+        );
+        let ethereum = HashSet::from([Chain::Ethereum]);
+        assert_eq!(
+            reconcile(&mut manifest, dir, &owned, &ethereum, Removal::Archive).unwrap(),
+            1
+        );
+        assert!(dir.join("kept.png").is_file());
+        assert!(dir.join("_archive/sold.png").is_file());
+        assert!(manifest.get("_archive/sold.png").is_some());
+        assert!(manifest.get("sold.png").is_none());
+
+        // Archived files are not archived again
+        assert_eq!(
+            reconcile(&mut manifest, dir, &owned, &ethereum, Removal::Prune).unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::chain::Chain;
+use ethers::types::U256;
+use ethers::utils::hex::encode;
+use serde::{Deserialize, Serialize};
+
+/// Media reference for a token as reported by the indexer
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(untagged)]
+#[serde(rename_all = "camelCase")]
+pub enum NftImage {
+    #[default]
+    Null,
+    Url(String),
+    Object {
+        url: String,
+        size: Option<serde_json::Value>,
+        mime_type: Option<String>,
+    },
+}
+/// Token standard implemented by the collection contract
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenStandard {
+    #[serde(rename = "ERC721")]
+    Erc721,
+    #[serde(rename = "ERC1155")]
+    Erc1155,
+}
+
+/// A single token owned by the account
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NftToken {
+    pub image: NftImage,
+    pub name: Option<String>,
+    pub collection_name: Option<String>,
+    /// Contract address of the collection
+    pub collection_address: Option<String>,
+    pub token_url: Option<String>,
+    pub token_id: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    #[serde(rename = "tokenStandard")]
+    pub standard: Option<TokenStandard>,
+    /// Editions held, for ERC-1155 tokens
+    pub balance: Option<u64>,
+    pub chain: Option<Chain>,
+}
+
+impl NftToken {
+    /// Name of the saved file, without extension
+    pub fn display_name(&self) -> Option<String> {
+        let name = if let Some(name) = &self.name {
+            name.clone()
+        } else if let (Some(collection_name), Some(id)) = (&self.collection_name, &self.token_id) {
+            format!("{} #{}", collection_name, id)
+        } else {
+            return None;
+        };
+        Some(name.replace(['/', '\\'], " "))
+    }
+
+    /// `animation_url` of the metadata: video, audio, 3D model or HTML media
+    pub fn animation_url(&self) -> Option<&str> {
+        self.metadata
+            .as_ref()?
+            .get("animation_url")
+            .and_then(|url| url.as_str())
+            .filter(|url| !url.is_empty())
+    }
+}
+
+/// Substitute the `{id}` placeholder of an ERC-1155 URI.
+///
+/// The spec requires the id as lowercase hex, zero padded to 64 characters, without `0x`.
+pub fn erc1155_uri(uri: &str, id: U256) -> String {
+    let mut bytes = [0u8; 32];
+    id.to_big_endian(&mut bytes);
+    uri.replace("{id}", &encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_erc1155_id() {
+        assert_eq!(
+            erc1155_uri("https://token-cdn-domain/{id}.json", U256::from(314592)),
+            "https://token-cdn-domain/000000000000000000000000000000000000000000000000000000000004cce0.json"
+        );
+    }
+}